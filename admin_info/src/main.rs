@@ -1,13 +1,12 @@
 #![forbid(unsafe_code)]
+mod role_service;
 mod service;
 
 use axum::{Extension, Router};
+use base_library::config::Config;
 use base_library::default_fallback;
-use dotenvy::dotenv;
 use mimalloc::MiMalloc;
 use sqlx::postgres::PgPoolOptions;
-use std::env;
-use std::net::SocketAddr;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -16,24 +15,22 @@ static GLOBAL: MiMalloc = MiMalloc;
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    dotenv().ok();
+    let config = Config::init(3000).expect("Configuration failed.");
 
     let db = PgPoolOptions::new()
-        .max_connections(80)
-        .connect(&format!(
-            "postgresql://{}:{}@localhost:5432/postgres",
-            env::var("DB_USER").unwrap_or_else(|_| "postgres".parse().unwrap()),
-            env::var("DB_PASS").unwrap_or_else(|_| "postgres".parse().unwrap())
-        ))
+        .max_connections(config.database.max_connections)
+        .min_connections(config.database.min_connections)
+        .connect(&config.database.url())
         .await
         .expect("Database connection failed.");
 
     let app = Router::new()
         .merge(service::router())
+        .merge(role_service::router())
         .layer(Extension(db))
         .fallback(default_fallback);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
+    let addr = config.bind_address();
 
     println!("Listening on {}", addr);
 
@@ -0,0 +1,222 @@
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::{delete, get, put};
+use axum::{Extension, Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use time::OffsetDateTime;
+
+use base_library::permission::{
+    RequirePermission, RoleDelete, RoleRead, RoleWrite, ALL_PERMISSIONS, SUPERUSER_ROLE,
+};
+use base_library::{default_fallback, err_json_gen, get_db_err, now_local_time, CustomJsonRequest};
+
+pub fn router() -> Router {
+    Router::new().nest(
+        "/admin/role",
+        Router::new()
+            .route("/list", get(list))
+            .route("/permissions", get(permissions))
+            .route("/query/:id", get(query))
+            .route("/save", put(save))
+            .route("/delete/:id", delete(remove))
+            .fallback(default_fallback),
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, sqlx::FromRow)]
+struct RoleInfo {
+    id: i32,
+    role_name: String,
+    permissions: Vec<String>,
+    note: Option<String>,
+    #[serde(with = "time::serde::iso8601")]
+    creation_timestamp: OffsetDateTime,
+    #[serde(with = "time::serde::iso8601")]
+    update_timestamp: OffsetDateTime,
+}
+
+#[derive(Deserialize)]
+struct RoleReq {
+    id: Option<i32>,
+    role_name: String,
+    permissions: Vec<String>,
+    note: Option<String>,
+}
+
+/// 查詢所有可指派的權限名稱
+async fn permissions(RequirePermission(_, _): RequirePermission<RoleRead>) -> impl IntoResponse {
+    Json::from(json!(ALL_PERMISSIONS))
+}
+
+/// 查詢角色列表
+async fn list(
+    RequirePermission(_, _): RequirePermission<RoleRead>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+) -> impl IntoResponse {
+    match sqlx::query_as!(RoleInfo, "select * from backendmodulesdb.role order by id;")
+        .fetch_all(db)
+        .await
+    {
+        Ok(result) => Ok(Json::from(json!(result))),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 查詢角色資訊
+async fn query(
+    RequirePermission(_, _): RequirePermission<RoleRead>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    match sqlx::query_as!(
+        RoleInfo,
+        "select * from backendmodulesdb.role where id = $1",
+        id
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(role_info) => Ok(Json::from(json!(role_info))),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 儲存角色（有提供ID的情況更新，無則新增），最高權限角色不可移除任何權限
+async fn save(
+    RequirePermission(_, _): RequirePermission<RoleWrite>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(params): CustomJsonRequest<RoleReq>,
+) -> impl IntoResponse {
+    let unknown_permissions: Vec<&str> = params
+        .permissions
+        .iter()
+        .map(String::as_str)
+        .filter(|permission| !ALL_PERMISSIONS.contains(permission))
+        .collect();
+    if !unknown_permissions.is_empty() {
+        return Err(err_json_gen(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some(format!(
+                "Unknown permission: {}.",
+                unknown_permissions.join(", ")
+            )),
+        ));
+    }
+    if params.id == Some(SUPERUSER_ROLE) {
+        let missing_permissions: Vec<&str> = ALL_PERMISSIONS
+            .iter()
+            .copied()
+            .filter(|permission| !params.permissions.iter().any(|p| p == permission))
+            .collect();
+        if !missing_permissions.is_empty() {
+            return Err(err_json_gen(
+                StatusCode::CONFLICT,
+                Some(format!(
+                    "The superuser role must keep every permission, missing: {}.",
+                    missing_permissions.join(", ")
+                )),
+            ));
+        }
+    }
+    let query = match params.id {
+        None => {
+            sqlx::query_as!(
+                RoleInfo,
+                r#"
+            insert into backendmodulesdb.role (
+                role_name,
+                permissions,
+                note,
+                creation_timestamp,
+                update_timestamp
+            )
+            values (
+                $1,
+                $2,
+                $3,
+                $4,
+                $4
+            ) returning *;
+            "#,
+                params.role_name,
+                &params.permissions,
+                params.note,
+                now_local_time()
+            )
+            .fetch_one(db)
+            .await
+        }
+        Some(id) => {
+            sqlx::query_as!(
+                RoleInfo,
+                r#"
+            update backendmodulesdb.role
+            set role_name = $2,
+                permissions = $3,
+                note = $4,
+                update_timestamp = $5
+            where id = $1 returning *;
+            "#,
+                id,
+                params.role_name,
+                &params.permissions,
+                params.note,
+                now_local_time()
+            )
+            .fetch_one(db)
+            .await
+        }
+    };
+    match query {
+        Ok(result) => Ok(Json::from(json!(result))),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 移除角色（最高權限角色或仍有管理員使用時拒絕）
+async fn remove(
+    RequirePermission(_, _): RequirePermission<RoleDelete>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    if id == SUPERUSER_ROLE {
+        return Err(err_json_gen(
+            StatusCode::CONFLICT,
+            Some("The superuser role cannot be deleted.".to_string()),
+        ));
+    }
+    match sqlx::query!(
+        "select count(*) from backendmodulesdb.admin_info where account_rule = $1",
+        id
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(record) => {
+            if record.count.unwrap_or(0) != 0 {
+                return Err(err_json_gen(
+                    StatusCode::CONFLICT,
+                    Some(
+                        "This role is still assigned to administrators, please reassign them first."
+                            .to_string(),
+                    ),
+                ));
+            }
+        }
+        Err(error) => return Err(get_db_err(error)),
+    }
+    match sqlx::query_as!(
+        RoleInfo,
+        "delete from backendmodulesdb.role where id = $1 returning *;",
+        id
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(role_info) => Ok((StatusCode::OK, role_info.id.to_string())),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
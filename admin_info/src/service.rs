@@ -1,19 +1,31 @@
 use axum::extract::{Path, Query};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::routing::{delete, get, post, put};
+use axum::routing::{delete, get, patch, post, put};
 use axum::{Extension, Json, Router};
-use jsonwebtoken::{encode, Header};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use sqlx::{Pool, Postgres};
 use time::OffsetDateTime;
 use uuid::Uuid;
 
+use base_library::account_token::{
+    consume_account_token, resend_allowed, send_token_mail, TokenPurpose,
+};
+use base_library::list_query::{AccountFilter, ListParams, ListQuery, ListSpec};
+use base_library::password::{
+    check_password_policy, hash_password, verify_password, PasswordCheck,
+};
+use base_library::patch::{patch_account, AccountPatchSpec};
+use base_library::permission::{
+    check_role_assignment, AdminDelete, AdminRead, AdminWrite, RequirePermission,
+};
+use base_library::token::{
+    issue_token_pair, revoke_account_sessions, revoke_session, rotate_refresh_token, TokenOwner,
+};
 use base_library::{
-    default_fallback, err_json_gen, get_db_err, get_jwt_exp_timestamp, new_uuid_v1, now_local_time,
-    pagination_offset, AdminToken, Claims, CustomJsonRequest, PaginationParams, PaginationResp,
-    ADMIN_KEY,
+    default_fallback, err_json_gen, get_db_err, new_uuid_v1, now_local_time, AdminToken,
+    CustomJsonRequest, PaginationParams,
 };
 
 pub fn router() -> Router {
@@ -22,6 +34,10 @@ pub fn router() -> Router {
             "/admin",
             Router::new()
                 .route("/login", post(login))
+                .route("/refresh", post(refresh))
+                .route("/logout", post(logout))
+                .route("/requestPasswordReset", post(request_password_reset))
+                .route("/resetPassword", post(reset_password))
                 .fallback(default_fallback),
         )
         .nest(
@@ -30,6 +46,7 @@ pub fn router() -> Router {
                 .route("/list", get(list))
                 .route("/query/:uuid", get(query))
                 .route("/save", put(save))
+                .route("/patch/:uuid", patch(partial_update))
                 .route("/delete/:uuid", delete(remove))
                 .route("/login", post(login))
                 .fallback(default_fallback),
@@ -47,6 +64,8 @@ struct AdminInfo {
     #[serde(default)]
     uuid: Uuid,
     login_account: String,
+    /// 新增時必填；更新時留空代表不變更。不會出現在回應內
+    #[serde(default, skip_serializing)]
     login_password: String,
     account_rule: i32,
     account_status: bool,
@@ -69,7 +88,7 @@ struct AdminInfo {
 
 /// 查詢管理員資訊
 async fn query(
-    AdminToken(_): AdminToken,
+    RequirePermission(_, _): RequirePermission<AdminRead>,
     Extension(ref db): Extension<Pool<Postgres>>,
     Path(search_uuid): Path<Uuid>,
 ) -> impl IntoResponse {
@@ -86,47 +105,91 @@ async fn query(
     }
 }
 
-/// 查詢管理員列表（分頁查詢，無提供參數則使用預設值）
+/// 管理員列表可搜尋與排序的欄位
+const ADMIN_LIST: ListSpec = ListSpec {
+    table: "backendmodulesdb.admin_info",
+    primary_key: "uuid",
+    search_columns: &["login_account", "user_name", "user_email"],
+    sort_columns: &[
+        "creation_timestamp",
+        "update_timestamp",
+        "login_account",
+        "user_name",
+        "user_email",
+        "account_rule",
+        "account_status",
+    ],
+    date_column: "creation_timestamp",
+};
+
+/// 查詢管理員列表（分頁查詢，無提供參數則使用預設值）；
+/// search比對帳號、名稱與email，可依account_status、account_rule與建立時間（from、to）篩選，sort、order指定排序
 async fn list(
-    AdminToken(_): AdminToken,
+    RequirePermission(_, _): RequirePermission<AdminRead>,
     Extension(ref db): Extension<Pool<Postgres>>,
     Query(params): Query<PaginationParams>,
+    Query(list_params): Query<ListParams>,
+    Query(filter): Query<AccountFilter>,
 ) -> impl IntoResponse {
-    let page = params.page.unwrap_or(1);
-    let count = params.count.unwrap_or(5);
-    let total = sqlx::query!("select count(*) from backendmodulesdb.admin_info;")
-        .fetch_one(db)
-        .await
-        .unwrap()
-        .count
-        .unwrap_or(0) as u64;
-    let offset = pagination_offset(page, count);
-    match sqlx::query_as!(
-        AdminInfo,
-        r#"select * from backendmodulesdb.admin_info order by creation_timestamp limit $1 offset $2;"#,
-        count as i64,
-        offset as i64
-    )
-        .fetch_all(db)
+    match ListQuery::new(&ADMIN_LIST, list_params)
+        .account_filter(filter)
+        .fetch_page::<AdminInfo>(db, &params)
         .await
     {
-        Ok(result) => Ok(Json::from(PaginationResp::new(
-            total,
-            count,
-            page,
-            json!(result),
-        ))),
-        Err(error) => Err(get_db_err(error)),
+        Ok(result) => Ok(Json::from(result)),
+        Err(error) => Err(error),
     }
 }
 
-/// 儲存管理員資訊（有提供UUID的情況更新，無則新增）
+/// 目前的角色與新的角色不同時（新增帳號時一律）檢查呼叫者是否可以指派該角色
+async fn check_account_rule_change(
+    db: &Pool<Postgres>,
+    caller: Uuid,
+    uuid: Option<Uuid>,
+    account_rule: i32,
+) -> Result<(), (StatusCode, Json<Value>)> {
+    let current = match uuid {
+        Some(uuid) => sqlx::query!(
+            "select account_rule from backendmodulesdb.admin_info where uuid = $1",
+            uuid
+        )
+        .fetch_optional(db)
+        .await
+        .map_err(get_db_err)?
+        .map(|record| record.account_rule),
+        None => None,
+    };
+    if current == Some(account_rule) {
+        return Ok(());
+    }
+    check_role_assignment(db, caller, account_rule).await
+}
+
+/// 儲存管理員資訊（有提供UUID的情況更新，無則新增），變更角色需要role.write權限
 async fn save(
-    AdminToken(_): AdminToken,
+    RequirePermission(admin_token, _): RequirePermission<AdminWrite>,
     Extension(ref db): Extension<Pool<Postgres>>,
     CustomJsonRequest(params): CustomJsonRequest<AdminInfo>,
 ) -> impl IntoResponse {
+    let uuid = (params.uuid != Uuid::default()).then_some(params.uuid);
+    check_account_rule_change(db, admin_token.uuid, uuid, params.account_rule).await?;
+    // 密碼需符合password設定的規則，以Argon2id雜湊後儲存
+    let login_password = if params.login_password.is_empty() {
+        None
+    } else {
+        check_password_policy(&params.login_password)?;
+        Some(hash_password(&params.login_password)?)
+    };
     if params.uuid == Uuid::default() {
+        let login_password = match login_password {
+            Some(login_password) => login_password,
+            None => {
+                return Err(err_json_gen(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Some("login_password is required when creating an account.".to_string()),
+                ))
+            }
+        };
         match sqlx::query!(
             "select count(*) from backendmodulesdb.admin_info where login_account = $1",
             params.login_account
@@ -166,7 +229,7 @@ async fn save(
         "#,
                         Uuid::from(new_uuid_v1()),
                         params.login_account,
-                        params.login_password,
+                        login_password,
                         params.account_rule,
                         params.account_status,
                         params.user_name,
@@ -199,7 +262,7 @@ async fn save(
             r#"
         update backendmodulesdb.admin_info
         set login_account = $2,
-            login_password = $3,
+            login_password = coalesce($3, login_password),
             account_rule = $4,
             account_status = $5,
             user_name = $6,
@@ -210,26 +273,68 @@ async fn save(
         "#,
             params.uuid,
             params.login_account,
-            params.login_password,
+            login_password,
             params.account_rule,
             params.account_status,
             params.user_name,
             params.user_email,
             params.note,
-            params.update_timestamp
+            now_local_time()
         )
         .fetch_one(db)
         .await;
         match query {
-            Ok(result) => Ok(Json::from(json!(result))),
+            Ok(result) => {
+                // 管理員變更密碼後撤銷該帳號所有的登入
+                if login_password.is_some() {
+                    revoke_account_sessions(db, TokenOwner::Admin, result.uuid).await?;
+                }
+                Ok(Json::from(json!(result)))
+            }
             Err(error) => Err(get_db_err(error)),
         }
     }
 }
 
+/// 管理員資訊可部分更新的欄位
+const ADMIN_PATCH: AccountPatchSpec = AccountPatchSpec {
+    table: "backendmodulesdb.admin_info",
+    owner: TokenOwner::Admin,
+    columns: &[
+        "login_account",
+        "account_rule",
+        "account_status",
+        "user_name",
+        "user_email",
+        "note",
+    ],
+};
+
+/// 部分更新管理員資訊（JSON Merge Patch，只更新有提供的欄位；login_password為null或未提供時不變更），
+/// 需附上查詢時取得的update_timestamp，資料已被其他請求修改時回傳409；變更角色需要role.write權限
+async fn partial_update(
+    RequirePermission(admin_token, _): RequirePermission<AdminWrite>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Path(uuid): Path<Uuid>,
+    CustomJsonRequest(patch): CustomJsonRequest<Value>,
+) -> impl IntoResponse {
+    // 型別錯誤的account_rule由patch_account回傳422
+    if let Some(account_rule) = patch
+        .get("account_rule")
+        .and_then(Value::as_i64)
+        .and_then(|account_rule| i32::try_from(account_rule).ok())
+    {
+        check_account_rule_change(db, admin_token.uuid, Some(uuid), account_rule).await?;
+    }
+    match patch_account::<AdminInfo>(db, &ADMIN_PATCH, uuid, patch).await {
+        Ok(result) => Ok(Json::from(result)),
+        Err(error) => Err(error),
+    }
+}
+
 /// 移除管理員
 async fn remove(
-    AdminToken(_): AdminToken,
+    RequirePermission(_, _): RequirePermission<AdminDelete>,
     Extension(ref db): Extension<Pool<Postgres>>,
     Path(uuid): Path<Uuid>,
 ) -> impl IntoResponse {
@@ -273,11 +378,18 @@ async fn login(
     .await
     {
         Ok(admin_vec) => {
-            let successful_count = admin_vec
+            let password = request.password.unwrap();
+            let matched_vec: Vec<(&AdminInfo, PasswordCheck)> = admin_vec
                 .iter()
-                .filter(|admin_info| admin_info.login_password == request.password.clone().unwrap())
-                .count();
-            if successful_count > 1 {
+                .map(|admin_info| {
+                    (
+                        admin_info,
+                        verify_password(&password, &admin_info.login_password),
+                    )
+                })
+                .filter(|(_, check)| *check != PasswordCheck::Mismatched)
+                .collect();
+            if matched_vec.len() > 1 {
                 Err(err_json_gen(
                     StatusCode::INTERNAL_SERVER_ERROR,
                     Some(
@@ -285,23 +397,34 @@ async fn login(
                             .to_string(),
                     ),
                 ))
-            } else if successful_count == 0 {
+            } else if matched_vec.is_empty() {
                 Err(err_json_gen(
                     StatusCode::UNAUTHORIZED,
                     Some("Couldn't found your account.".to_string()),
                 ))
             } else {
-                return if admin_vec.first().unwrap().account_status {
-                    let claims = Claims {
-                        uuid: admin_vec.first().unwrap().uuid.to_owned(),
-                        exp: get_jwt_exp_timestamp(),
+                let (admin_info, check) = matched_vec.first().unwrap();
+                // 舊版明文密碼（或舊參數的雜湊）在登入成功時重新雜湊寫回
+                if *check == PasswordCheck::NeedsRehash {
+                    let login_password = match hash_password(&password) {
+                        Ok(login_password) => login_password,
+                        Err(error) => return Err(error),
                     };
-                    match encode(&Header::default(), &claims, &ADMIN_KEY.encoding) {
-                        Ok(token) => Ok((StatusCode::OK, token)),
-                        Err(error) => Err(err_json_gen(
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            Some(error.to_string()),
-                        )),
+                    if let Err(error) = sqlx::query!(
+                        "update backendmodulesdb.admin_info set login_password = $2 where uuid = $1",
+                        admin_info.uuid,
+                        login_password
+                    )
+                    .execute(db)
+                    .await
+                    {
+                        return Err(get_db_err(error));
+                    }
+                }
+                return if admin_info.account_status {
+                    match issue_token_pair(db, TokenOwner::Admin, admin_info.uuid, None).await {
+                        Ok(token_pair) => Ok(Json::from(json!(token_pair))),
+                        Err(error) => Err(error),
                     }
                 } else {
                     Err(err_json_gen(
@@ -314,3 +437,251 @@ async fn login(
         Err(error) => Err(get_db_err(error)),
     }
 }
+
+#[derive(Deserialize)]
+struct RefreshReq {
+    refresh_token: String,
+}
+
+/// 以Refresh Token換發新的Token（舊的Refresh Token隨即失效）
+async fn refresh(
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(request): CustomJsonRequest<RefreshReq>,
+) -> impl IntoResponse {
+    match rotate_refresh_token(db, TokenOwner::Admin, &request.refresh_token).await {
+        Ok(token_pair) => Ok(Json::from(json!(token_pair))),
+        Err(error) => Err(error),
+    }
+}
+
+/// 登出（撤銷目前的Access Token與同一次登入換發的所有Refresh Token）
+async fn logout(
+    AdminToken(admin_token): AdminToken,
+    Extension(ref db): Extension<Pool<Postgres>>,
+) -> impl IntoResponse {
+    match revoke_session(db, &admin_token).await {
+        Ok(()) => Ok((StatusCode::OK, admin_token.uuid.to_string())),
+        Err(error) => Err(error),
+    }
+}
+
+#[derive(Deserialize)]
+struct RequestPasswordResetReq {
+    account: String,
+}
+
+/// 要求重設密碼，寄出含一次性連結的郵件到管理員的email（同一帳號在account.resend_interval秒內只寄送一次），
+/// 無論帳號是否存在、已停用或沒有email都回傳相同結果，避免被用來探測帳號
+async fn request_password_reset(
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(request): CustomJsonRequest<RequestPasswordResetReq>,
+) -> impl IntoResponse {
+    let admin_vec = match sqlx::query_as!(
+        AdminInfo,
+        "select * from backendmodulesdb.admin_info where login_account = $1 and account_status = true and user_email is not null",
+        request.account
+    )
+    .fetch_all(db)
+    .await
+    {
+        Ok(admin_vec) => admin_vec,
+        Err(error) => return Err(get_db_err(error)),
+    };
+    for admin_info in admin_vec {
+        let user_email = match &admin_info.user_email {
+            Some(user_email) => user_email,
+            None => continue,
+        };
+        if resend_allowed(
+            db,
+            TokenOwner::Admin,
+            admin_info.uuid,
+            TokenPurpose::ResetPassword,
+        )
+        .await?
+        {
+            send_token_mail(
+                db,
+                TokenOwner::Admin,
+                admin_info.uuid,
+                TokenPurpose::ResetPassword,
+                user_email,
+                &admin_info.user_name,
+            )
+            .await?;
+        }
+    }
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "reason": "If the account exists, a password reset mail has been sent."
+        })),
+    ))
+}
+
+#[derive(Deserialize)]
+struct ResetPasswordReq {
+    token: String,
+    password: String,
+}
+
+/// 以重設密碼郵件內的Token設定新密碼（Token只能使用一次），完成後撤銷該管理員的所有登入
+async fn reset_password(
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(request): CustomJsonRequest<ResetPasswordReq>,
+) -> impl IntoResponse {
+    check_password_policy(&request.password)?;
+    let login_password = hash_password(&request.password)?;
+    let uuid = match consume_account_token(
+        db,
+        TokenOwner::Admin,
+        TokenPurpose::ResetPassword,
+        &request.token,
+    )
+    .await?
+    {
+        Some(uuid) => uuid,
+        None => {
+            return Err(err_json_gen(
+                StatusCode::BAD_REQUEST,
+                Some(
+                    "Password reset token is invalid, used or expired. Please request a new password reset mail."
+                        .to_string(),
+                ),
+            ))
+        }
+    };
+    if let Err(error) = sqlx::query!(
+        "update backendmodulesdb.admin_info set login_password = $2, update_timestamp = $3 where uuid = $1",
+        uuid,
+        login_password,
+        now_local_time()
+    )
+    .execute(db)
+    .await
+    {
+        return Err(get_db_err(error));
+    }
+    revoke_account_sessions(db, TokenOwner::Admin, uuid).await?;
+    Ok((StatusCode::OK, uuid.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{header, Request};
+    use base_library::config::Config;
+    use sqlx::postgres::PgPoolOptions;
+    use tower::ServiceExt;
+
+    /// 新增一個啟用中的管理員
+    async fn insert_admin(db: &Pool<Postgres>, account_rule: i32) -> Uuid {
+        let uuid = new_uuid_v1();
+        sqlx::query(
+            r#"
+            insert into backendmodulesdb.admin_info (
+                uuid, login_account, login_password, account_rule, account_status,
+                user_name, creation_timestamp, update_timestamp
+            )
+            values ($1, $2, '', $3, true, 'test', now(), now());
+            "#,
+        )
+        .bind(uuid)
+        .bind(format!("role_test_{}", uuid))
+        .bind(account_rule)
+        .execute(db)
+        .await
+        .unwrap();
+        uuid
+    }
+
+    async fn send(app: Router, method: &str, uri: String, token: &str, body: Value) -> StatusCode {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    /// 需要PostgreSQL（DATABASE_URL），以`cargo test -- --ignored`執行
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn admin_write_alone_cannot_change_account_rule() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set.");
+        let db = PgPoolOptions::new()
+            .max_connections(2)
+            .connect(&url)
+            .await
+            .unwrap();
+        Config::init_with(Config::default());
+        let role: i32 = sqlx::query_scalar(
+            "insert into backendmodulesdb.role (role_name, permissions) values ($1, $2) returning id;",
+        )
+        .bind(format!("admin_writer_{}", new_uuid_v1()))
+        .bind(vec!["admin.write".to_string()])
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        let caller = insert_admin(&db, role).await;
+        let target = insert_admin(&db, role).await;
+        let token = issue_token_pair(&db, TokenOwner::Admin, caller, None)
+            .await
+            .unwrap()
+            .access_token;
+        let app = router().layer(Extension(db.clone()));
+
+        let patch_status = send(
+            app.clone(),
+            "PATCH",
+            format!("/admin/management/patch/{}", caller),
+            &token,
+            json!({ "account_rule": 0 }),
+        )
+        .await;
+        let save_status = send(
+            app,
+            "PUT",
+            "/admin/management/save".to_string(),
+            &token,
+            json!({
+                "uuid": target,
+                "login_account": format!("role_test_{}", target),
+                "account_rule": 0,
+                "account_status": true,
+                "user_name": "test"
+            }),
+        )
+        .await;
+        let account_rules: Vec<i32> = sqlx::query_scalar(
+            "select account_rule from backendmodulesdb.admin_info where uuid = any($1);",
+        )
+        .bind(vec![caller, target])
+        .fetch_all(&db)
+        .await
+        .unwrap();
+
+        sqlx::query("delete from backendmodulesdb.refresh_token where account_uuid = $1;")
+            .bind(caller)
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query("delete from backendmodulesdb.admin_info where uuid = any($1);")
+            .bind(vec![caller, target])
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query("delete from backendmodulesdb.role where id = $1;")
+            .bind(role)
+            .execute(&db)
+            .await
+            .unwrap();
+
+        assert_eq!(patch_status, StatusCode::FORBIDDEN);
+        assert_eq!(save_status, StatusCode::FORBIDDEN);
+        assert_eq!(account_rules, [role, role]);
+    }
+}
@@ -0,0 +1,26 @@
+-- Refresh Token（只保存SHA-256雜湊值），同一次登入換發出的Token屬於同一個family
+create table if not exists backendmodulesdb.refresh_token
+(
+    token_hash         varchar(64) primary key,
+    family_uuid        uuid                     not null,
+    account_uuid       uuid                     not null,
+    account_type       varchar(16)              not null,
+    access_jti         uuid                     not null,
+    used               boolean                  not null default false,
+    revoked            boolean                  not null default false,
+    expire_timestamp   timestamp with time zone not null,
+    creation_timestamp timestamp with time zone not null default now()
+);
+
+create index if not exists refresh_token_family_uuid_index
+    on backendmodulesdb.refresh_token (family_uuid);
+
+create index if not exists refresh_token_access_jti_index
+    on backendmodulesdb.refresh_token (access_jti);
+
+-- 已撤銷的Access Token（jti），過期後即可刪除
+create table if not exists backendmodulesdb.revoked_token
+(
+    jti              uuid primary key,
+    expire_timestamp timestamp with time zone not null
+);
@@ -0,0 +1,19 @@
+-- 角色與權限，admin_info.account_rule對應role.id
+create table if not exists backendmodulesdb.role
+(
+    id                 serial primary key,
+    role_name          varchar(64)              not null unique,
+    permissions        text[]                   not null default '{}',
+    note               text,
+    creation_timestamp timestamp with time zone not null default now(),
+    update_timestamp   timestamp with time zone not null default now()
+);
+
+-- 既有管理員的account_rule預設為0，保留原本可存取所有管理API的行為
+insert into backendmodulesdb.role (id, role_name, permissions)
+values (0, 'administrator', array [
+    'admin.read', 'admin.write', 'admin.delete',
+    'user.read', 'user.write', 'user.delete',
+    'role.read', 'role.write', 'role.delete'
+    ])
+on conflict do nothing;
@@ -0,0 +1,20 @@
+-- 通知發送佇列（outbox），由notification服務的worker取出發送
+-- state：pending（等待發送或重試）、processing（worker處理中，locked_until前不會被其他worker取走）、sent（已送出）、dead（超過重試次數或無法重試）
+create table if not exists backendmodulesdb.notification_job
+(
+    id                 uuid primary key,
+    channel            varchar(16)              not null,
+    payload            jsonb                    not null,
+    state              varchar(16)              not null default 'pending',
+    attempts           integer                  not null default 0,
+    max_attempts       integer                  not null,
+    next_attempt_at    timestamp with time zone not null default now(),
+    locked_until       timestamp with time zone,
+    last_error         text,
+    creation_timestamp timestamp with time zone not null default now(),
+    update_timestamp   timestamp with time zone not null default now()
+);
+
+create index if not exists notification_job_next_attempt_at_index
+    on backendmodulesdb.notification_job (next_attempt_at)
+    where state in ('pending', 'processing');
@@ -0,0 +1,23 @@
+-- 通知每次發送的結果，code為SMTP回應碼或APNs的HTTP狀態碼，reason為SMTP回應內容或APNs的錯誤原因
+create table if not exists backendmodulesdb.notification_attempt
+(
+    id                 bigserial primary key,
+    job_id             uuid                     not null references backendmodulesdb.notification_job (id) on delete cascade,
+    attempt            integer                  not null,
+    success            boolean                  not null,
+    code               varchar(16),
+    reason             text,
+    creation_timestamp timestamp with time zone not null default now()
+);
+
+create index if not exists notification_attempt_job_id_index
+    on backendmodulesdb.notification_attempt (job_id);
+
+create index if not exists notification_job_creation_timestamp_index
+    on backendmodulesdb.notification_job (creation_timestamp);
+
+-- 查詢通知列表的權限，預設角色一併擁有
+update backendmodulesdb.role
+set permissions = array_append(permissions, 'notification.read')
+where id = 0
+  and not 'notification.read' = any (permissions);
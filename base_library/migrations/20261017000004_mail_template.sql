@@ -0,0 +1,20 @@
+-- 郵件範本，同一個範本名稱可有多個語系（locale如zh-TW、en），subject、text_body與html_body使用Jinja語法
+create table if not exists backendmodulesdb.mail_template
+(
+    id                 serial primary key,
+    template_name      varchar(64)              not null,
+    locale             varchar(16)              not null,
+    subject            text                     not null,
+    text_body          text                     not null,
+    html_body          text,
+    note               text,
+    creation_timestamp timestamp with time zone not null default now(),
+    update_timestamp   timestamp with time zone not null default now(),
+    unique (template_name, locale)
+);
+
+-- 管理郵件範本的權限，預設角色一併擁有
+update backendmodulesdb.role
+set permissions = permissions || array ['template.read', 'template.write', 'template.delete']
+where id = 0
+  and not 'template.read' = any (permissions);
@@ -0,0 +1,14 @@
+-- 用戶的推播裝置，同一個裝置Token只屬於最後登記的用戶；APNs回覆Unregistered或BadDeviceToken時自動刪除
+create table if not exists backendmodulesdb.device_token
+(
+    device_token       varchar(200) primary key,
+    user_uuid          uuid                     not null,
+    platform           varchar(16)              not null,
+    bundle_id          varchar(255)             not null,
+    locale             varchar(16),
+    creation_timestamp timestamp with time zone not null default now(),
+    update_timestamp   timestamp with time zone not null default now()
+);
+
+create index if not exists device_token_user_uuid_index
+    on backendmodulesdb.device_token (user_uuid);
@@ -0,0 +1,25 @@
+-- 推播主題，用戶訂閱後可收到發送給該主題的推播
+create table if not exists backendmodulesdb.notification_topic
+(
+    topic_name         varchar(64) primary key,
+    note               text,
+    creation_timestamp timestamp with time zone not null default now(),
+    update_timestamp   timestamp with time zone not null default now()
+);
+
+create table if not exists backendmodulesdb.topic_subscription
+(
+    topic_name         varchar(64)              not null references backendmodulesdb.notification_topic (topic_name) on delete cascade,
+    user_uuid          uuid                     not null,
+    creation_timestamp timestamp with time zone not null default now(),
+    primary key (topic_name, user_uuid)
+);
+
+create index if not exists topic_subscription_user_uuid_index
+    on backendmodulesdb.topic_subscription (user_uuid);
+
+-- 管理推播主題的權限，預設角色一併擁有
+update backendmodulesdb.role
+set permissions = permissions || array ['topic.write']
+where id = 0
+  and not 'topic.write' = any (permissions);
@@ -0,0 +1,18 @@
+-- 其他服務呼叫notification服務時使用的API Key，只保存SHA-256雜湊值；scopes為可使用的通知管道（mail、apns）
+create table if not exists backendmodulesdb.notification_api_key
+(
+    id                  uuid primary key,
+    key_name            varchar(64)              not null,
+    key_hash            varchar(64)              not null unique,
+    scopes              varchar(16)[]            not null default '{}',
+    key_status          boolean                  not null default true,
+    last_used_timestamp timestamp with time zone,
+    creation_timestamp  timestamp with time zone not null default now(),
+    update_timestamp    timestamp with time zone not null default now()
+);
+
+-- 管理API Key的權限，預設角色一併擁有
+update backendmodulesdb.role
+set permissions = permissions || array ['api_key.read', 'api_key.write', 'api_key.delete']
+where id = 0
+  and not 'api_key.read' = any (permissions);
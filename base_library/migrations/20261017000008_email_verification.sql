@@ -0,0 +1,34 @@
+-- 新註冊的用戶需要驗證email才能登入，既有帳號視為已驗證
+alter table backendmodulesdb.user_info
+    add column if not exists email_verified boolean not null default true;
+
+-- 一次性Token（email驗證等），只保存SHA-256雜湊值，使用後或過期即失效
+create table if not exists backendmodulesdb.account_token
+(
+    token_hash         varchar(64) primary key,
+    account_uuid       uuid                     not null,
+    account_type       varchar(16)              not null,
+    purpose            varchar(32)              not null,
+    used               boolean                  not null default false,
+    expire_timestamp   timestamp with time zone not null,
+    creation_timestamp timestamp with time zone not null default now()
+);
+
+create index if not exists account_token_account_index
+    on backendmodulesdb.account_token (account_uuid, account_type, purpose);
+
+-- 驗證信範本（可透過/notification/template修改），變數：user_name、link、token
+insert into backendmodulesdb.mail_template (template_name, locale, subject, text_body, html_body, note)
+values ('account_verification',
+        'en',
+        'Verify your email address',
+        e'Hi {{ user_name }},\n\nPlease verify your email address by opening the link below:\n\n{{ link }}\n\nIf you did not create an account, you can ignore this mail.',
+        '<p>Hi {{ user_name }},</p><p>Please verify your email address by opening the link below:</p><p><a href="{{ link }}">{{ link }}</a></p><p>If you did not create an account, you can ignore this mail.</p>',
+        'Sent by user_info after registration'),
+       ('account_verification',
+        'zh-TW',
+        '請驗證您的電子郵件地址',
+        e'{{ user_name }} 您好：\n\n請開啟以下連結驗證您的電子郵件地址：\n\n{{ link }}\n\n如果您沒有註冊帳號，請忽略這封郵件。',
+        '<p>{{ user_name }} 您好：</p><p>請開啟以下連結驗證您的電子郵件地址：</p><p><a href="{{ link }}">{{ link }}</a></p><p>如果您沒有註冊帳號，請忽略這封郵件。</p>',
+        'Sent by user_info after registration')
+on conflict (template_name, locale) do nothing;
@@ -0,0 +1,15 @@
+-- 重設密碼郵件範本（可透過/notification/template修改），變數：user_name、link、token、expires_in（分鐘）
+insert into backendmodulesdb.mail_template (template_name, locale, subject, text_body, html_body, note)
+values ('password_reset',
+        'en',
+        'Reset your password',
+        e'Hi {{ user_name }},\n\nWe received a request to reset your password. Open the link below within {{ expires_in }} minutes to choose a new one:\n\n{{ link }}\n\nIf you did not request a password reset, you can ignore this mail and your password will stay the same.',
+        '<p>Hi {{ user_name }},</p><p>We received a request to reset your password. Open the link below within {{ expires_in }} minutes to choose a new one:</p><p><a href="{{ link }}">{{ link }}</a></p><p>If you did not request a password reset, you can ignore this mail and your password will stay the same.</p>',
+        'Sent by user_info and admin_info when a password reset is requested'),
+       ('password_reset',
+        'zh-TW',
+        '重設您的密碼',
+        e'{{ user_name }} 您好：\n\n我們收到了重設密碼的要求，請在 {{ expires_in }} 分鐘內開啟以下連結設定新密碼：\n\n{{ link }}\n\n如果您沒有要求重設密碼，請忽略這封郵件，您的密碼不會變更。',
+        '<p>{{ user_name }} 您好：</p><p>我們收到了重設密碼的要求，請在 {{ expires_in }} 分鐘內開啟以下連結設定新密碼：</p><p><a href="{{ link }}">{{ link }}</a></p><p>如果您沒有要求重設密碼，請忽略這封郵件，您的密碼不會變更。</p>',
+        'Sent by user_info and admin_info when a password reset is requested')
+on conflict (template_name, locale) do nothing;
@@ -0,0 +1,5 @@
+-- 建立通知的呼叫者（API Key ID或管理員UUID，擇一），查詢、取消與重新排程通知時只能存取自己建立的通知
+-- 既有的通知沒有呼叫者，只有擁有notification.read權限的管理員可以存取
+alter table backendmodulesdb.notification_job
+    add column if not exists created_by_api_key uuid,
+    add column if not exists created_by_admin   uuid;
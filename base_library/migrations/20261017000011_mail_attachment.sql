@@ -0,0 +1,13 @@
+-- 批次寄信的附件與內嵌圖片只保存一份，各收件人的通知（notification_job.payload）以attachment_id引用；
+-- 引用的通知都已結束（送出、失敗或取消）後刪除
+create table if not exists backendmodulesdb.mail_attachment
+(
+    id                 uuid primary key,
+    inline_images      jsonb                    not null default '[]',
+    attachments        jsonb                    not null default '[]',
+    creation_timestamp timestamp with time zone not null default now()
+);
+
+create index if not exists notification_job_attachment_id_index
+    on backendmodulesdb.notification_job ((payload ->> 'attachment_id'))
+    where payload ? 'attachment_id';
@@ -0,0 +1,192 @@
+use crate::config::config;
+use crate::notification_client::send_template_mail;
+use crate::token::{hash_token, new_opaque_token, TokenOwner};
+use crate::{get_db_err, now_local_time};
+use axum::http::StatusCode;
+use axum::Json;
+use serde_json::{json, Value};
+use sqlx::types::Uuid;
+use sqlx::{Pool, Postgres};
+use time::OffsetDateTime;
+
+/// 一次性Token的用途
+#[derive(Clone, Copy)]
+pub enum TokenPurpose {
+    /// 驗證email
+    VerifyEmail,
+    /// 重設密碼
+    ResetPassword,
+}
+
+impl TokenPurpose {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::VerifyEmail => "verify_email",
+            TokenPurpose::ResetPassword => "reset_password",
+        }
+    }
+
+    /// 寄送Token使用的郵件範本、連結與有效秒數
+    fn mail(&self, owner: TokenOwner) -> (&'static str, &'static str, u64) {
+        let account = &config().account;
+        match (self, owner) {
+            (TokenPurpose::VerifyEmail, _) => (
+                "account_verification",
+                &account.verify_url,
+                account.verification_ttl,
+            ),
+            (TokenPurpose::ResetPassword, TokenOwner::User) => {
+                ("password_reset", &account.reset_url, account.reset_ttl)
+            }
+            (TokenPurpose::ResetPassword, TokenOwner::Admin) => (
+                "password_reset",
+                &account.admin_reset_url,
+                account.reset_ttl,
+            ),
+        }
+    }
+}
+
+/// 簽發一次性Token，並在背景透過notification服務寄出含連結的郵件（寄送失敗只記錄警告，可再要求重新寄送）
+///
+/// 範本變數：user_name、link、token、expires_in（分鐘）
+pub async fn send_token_mail(
+    db: &Pool<Postgres>,
+    owner: TokenOwner,
+    account_uuid: Uuid,
+    purpose: TokenPurpose,
+    to_address: &str,
+    user_name: &str,
+) -> Result<(), (StatusCode, Json<Value>)> {
+    let (template, url, ttl) = purpose.mail(owner);
+    let token = issue_account_token(db, owner, account_uuid, purpose, ttl).await?;
+    let to_address = to_address.to_string();
+    let variables = json!({
+        "user_name": user_name,
+        "link": url.replace("{token}", &token),
+        "token": token,
+        "expires_in": ttl / 60
+    });
+    tokio::spawn(async move {
+        if let Err(error) = send_template_mail(&to_address, template, variables).await {
+            tracing::warn!("Unable to send {} mail: {}", template, error);
+        }
+    });
+    Ok(())
+}
+
+/// 距離上次簽發同用途的Token是否已超過account.resend_interval秒（限制重新寄送的頻率）
+pub async fn resend_allowed(
+    db: &Pool<Postgres>,
+    owner: TokenOwner,
+    account_uuid: Uuid,
+    purpose: TokenPurpose,
+) -> Result<bool, (StatusCode, Json<Value>)> {
+    let interval = time::Duration::seconds(config().account.resend_interval as i64);
+    Ok(match last_issued(db, owner, account_uuid, purpose).await? {
+        Some(last_issued) => last_issued + interval <= now_local_time(),
+        None => true,
+    })
+}
+
+/// 簽發一次性Token（只保存雜湊值），同一帳號同一用途尚未使用的舊Token隨即失效
+pub async fn issue_account_token(
+    db: &Pool<Postgres>,
+    owner: TokenOwner,
+    account_uuid: Uuid,
+    purpose: TokenPurpose,
+    ttl: u64,
+) -> Result<String, (StatusCode, Json<Value>)> {
+    let token = new_opaque_token();
+    let mut transaction = db.begin().await.map_err(get_db_err)?;
+    // 順便清除已過期的Token
+    sqlx::query!("delete from backendmodulesdb.account_token where expire_timestamp < now()")
+        .execute(&mut transaction)
+        .await
+        .map_err(get_db_err)?;
+    sqlx::query!(
+        r#"
+        update backendmodulesdb.account_token
+        set used = true
+        where account_uuid = $1 and account_type = $2 and purpose = $3 and used = false;
+        "#,
+        account_uuid,
+        owner.as_str(),
+        purpose.as_str()
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(get_db_err)?;
+    sqlx::query!(
+        r#"
+        insert into backendmodulesdb.account_token (
+            token_hash,
+            account_uuid,
+            account_type,
+            purpose,
+            expire_timestamp
+        )
+        values ($1, $2, $3, $4, now() + make_interval(secs => $5));
+        "#,
+        hash_token(&token),
+        account_uuid,
+        owner.as_str(),
+        purpose.as_str(),
+        ttl as f64
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(get_db_err)?;
+    transaction.commit().await.map_err(get_db_err)?;
+    Ok(token)
+}
+
+/// 使用一次性Token，成功時回傳帳號UUID；Token不存在、已使用或已過期時回傳None
+pub async fn consume_account_token(
+    db: &Pool<Postgres>,
+    owner: TokenOwner,
+    purpose: TokenPurpose,
+    token: &str,
+) -> Result<Option<Uuid>, (StatusCode, Json<Value>)> {
+    sqlx::query!(
+        r#"
+        update backendmodulesdb.account_token
+        set used = true
+        where token_hash = $1
+            and account_type = $2
+            and purpose = $3
+            and used = false
+            and expire_timestamp > now()
+        returning account_uuid;
+        "#,
+        hash_token(token),
+        owner.as_str(),
+        purpose.as_str()
+    )
+    .fetch_optional(db)
+    .await
+    .map(|record| record.map(|record| record.account_uuid))
+    .map_err(get_db_err)
+}
+
+/// 帳號最近一次簽發該用途Token的時間（限制重新寄送的頻率）
+pub async fn last_issued(
+    db: &Pool<Postgres>,
+    owner: TokenOwner,
+    account_uuid: Uuid,
+    purpose: TokenPurpose,
+) -> Result<Option<OffsetDateTime>, (StatusCode, Json<Value>)> {
+    sqlx::query!(
+        r#"
+        select max(creation_timestamp) from backendmodulesdb.account_token
+        where account_uuid = $1 and account_type = $2 and purpose = $3;
+        "#,
+        account_uuid,
+        owner.as_str(),
+        purpose.as_str()
+    )
+    .fetch_one(db)
+    .await
+    .map(|record| record.max)
+    .map_err(get_db_err)
+}
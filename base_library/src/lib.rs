@@ -2,6 +2,15 @@
 #![forbid(unsafe_code)]
 extern crate core;
 
+pub mod account_token;
+pub mod config;
+pub mod list_query;
+pub mod notification_client;
+pub mod password;
+pub mod patch;
+pub mod permission;
+pub mod token;
+
 use axum::extract::rejection::JsonRejection;
 use axum::extract::{FromRequest, FromRequestParts};
 use axum::headers::authorization::Bearer;
@@ -10,16 +19,17 @@ use axum::http::request::Parts;
 use axum::http::{Request, StatusCode};
 use axum::response::{Html, IntoResponse};
 use axum::{async_trait, Json, TypedHeader};
-use jsonwebtoken::{decode, DecodingKey, EncodingKey, Validation};
+use config::config;
+use jsonwebtoken::{DecodingKey, EncodingKey};
 use once_cell::sync::Lazy;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use sqlx::types::Uuid;
 use sqlx::Error;
-use std::env;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use time::OffsetDateTime;
+use token::TokenOwner;
 use uuid::{Context, Timestamp};
 
 pub const DEFAULT_FALLBACK_HTML: &str = r#"<!DOCTYPE html>
@@ -34,6 +44,9 @@ pub const BAD_REQUEST_MSG: &str = "You sent a request that this server could not
 
 pub const UNAUTHORIZED_MSG: &str = "This server could not verify that you are authorized to access the document requested. Either you supplied the wrong credentials (e.g., bad password), or server doesn't understand how to supply the credentials required.";
 
+pub const FORBIDDEN_MSG: &str =
+    "You don't have permission to access the requested resource on this server.";
+
 pub const NOT_FOUND_MSG: &str = "The requested resources was not found on this server.";
 
 pub const UNSUPPORTED_MEDIA_TYPE_MSG: &str = "The server refused this request because the request entity is in a format not supported by the requested resource for the requested method.";
@@ -46,15 +59,9 @@ pub const GATEWAY_TIMEOUT_MSG: &str = "The server didn't respond in time. Please
 
 pub const INTERNAL_SERVER_ERROR_MSG: &str = "The server encountered an internal error or misconfiguration and was unable to complete your request. Please contact the server administrator, mail@mingchang.tw and inform them of the time the error occurred, and anything you might have done that may have caused the error.";
 
-pub static ADMIN_KEY: Lazy<Keys> = Lazy::new(|| {
-    let secret = env::var("ADMIN_JWT_SECRET").unwrap_or_else(|_| "7W0nwzmIeFBj6gd-tZpwHw7tTZ8KJ9vp0fvhU9chRqcD74dPJy_KV_cqxFyjpmvEC0AJSENbMC5Pq03BfIA4mLR3pd_h1vKoB4mestDn0cx6gKULZXBVSTa3fUdvxGzDxY_IDRUUlpGRWp6loprqyvliO8aw0BzkN2BPD8qRN8M".to_owned());
-    Keys::new(secret.as_bytes())
-});
+pub static ADMIN_KEY: Lazy<Keys> = Lazy::new(|| Keys::new(config().jwt.admin_secret.as_bytes()));
 
-pub static USER_KEY: Lazy<Keys> = Lazy::new(|| {
-    let secret = env::var("USER_JWT_SECRET").unwrap_or_else(|_| "8W0nwzmIeFBj6gd-tZpwHw7tTZ8KJ9vp0fvhU9chRqcD74dPJy_KV_cqxFyjpmvEC0AJSENbMC5Pq03BfIA4mLR3pd_h1vKoB4mestDn0cx6gKULZXBVSTa3fUdvxGzDxY_IDRUUlpGRWp6loprqyvliO8aw0BzkN2BPD8qRN9N".to_owned());
-    Keys::new(secret.as_bytes())
-});
+pub static USER_KEY: Lazy<Keys> = Lazy::new(|| Keys::new(config().jwt.user_secret.as_bytes()));
 
 pub struct Keys {
     pub encoding: EncodingKey,
@@ -107,6 +114,7 @@ where
 #[derive(Deserialize, Serialize)]
 pub struct Claims {
     pub uuid: Uuid,
+    pub jti: Uuid,
     pub exp: u64,
 }
 
@@ -130,31 +138,10 @@ where
                         Some("Unable to extract token from request. Please log in.".to_string()),
                     )
                 })?;
-        let token_data =
-            decode::<AdminToken>(bearer.token(), &ADMIN_KEY.decoding, &Validation::default())
-                .map_err(|_| {
-                    err_json_gen(
-                        StatusCode::UNAUTHORIZED,
-                        Some(
-                            "Unable to parse token. This API needs a token for an administrator."
-                                .to_string(),
-                        ),
-                    )
-                })?;
-
-        if token_data.claims.0.exp
-            < SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs()
-        {
-            Err(err_json_gen(
-                StatusCode::UNAUTHORIZED,
-                Some("Token expired, please log in again.".to_string()),
-            ))
-        } else {
-            Ok(token_data.claims)
-        }
+        let db = token::token_db(parts, state).await?;
+        token::verify_token(TokenOwner::Admin, bearer.token(), &db)
+            .await
+            .map(AdminToken)
     }
 }
 
@@ -178,31 +165,10 @@ where
                         Some("Unable to extract token from request. Please log in.".to_string()),
                     )
                 })?;
-        let token_data =
-            decode::<UserToken>(bearer.token(), &USER_KEY.decoding, &Validation::default())
-                .map_err(|_| {
-                    err_json_gen(
-                        StatusCode::UNAUTHORIZED,
-                        Some(
-                            "Unable to parse token. This API needs a token for an user."
-                                .to_string(),
-                        ),
-                    )
-                })?;
-
-        if token_data.claims.0.exp
-            < SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs()
-        {
-            Err(err_json_gen(
-                StatusCode::UNAUTHORIZED,
-                Some("Token expired, please log in again.".to_string()),
-            ))
-        } else {
-            Ok(token_data.claims)
-        }
+        let db = token::token_db(parts, state).await?;
+        token::verify_token(TokenOwner::User, bearer.token(), &db)
+            .await
+            .map(UserToken)
     }
 }
 
@@ -239,6 +205,14 @@ pub fn err_json_gen(status_code: StatusCode, reason: Option<String>) -> (StatusC
                 reason
             ))),
         ),
+        StatusCode::FORBIDDEN => (
+            status_code,
+            Json::from(json!(ErrJson::new(
+                status_code,
+                FORBIDDEN_MSG.to_string(),
+                reason
+            ))),
+        ),
         StatusCode::UNPROCESSABLE_ENTITY => (
             status_code,
             Json::from(json!(ErrJson::new(
@@ -364,9 +338,9 @@ pub fn now_local_time() -> OffsetDateTime {
         .unwrap_or_else(OffsetDateTime::now_utc)
 }
 
-/// 產生JWT過期時間（目前設定一天）
+/// 產生JWT過期時間（依設定的access_token_ttl，預設十五分鐘，過期後以Refresh Token換發）
 pub fn get_jwt_exp_timestamp() -> u64 {
     let now = SystemTime::now();
     let since_the_epoch = now.duration_since(UNIX_EPOCH).unwrap();
-    (since_the_epoch + Duration::from_secs(86400)).as_secs()
+    (since_the_epoch + Duration::from_secs(config().jwt.access_token_ttl)).as_secs()
 }
@@ -0,0 +1,372 @@
+use crate::{err_json_gen, get_db_err, pagination_offset, PaginationParams, PaginationResp};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Pool, Postgres, QueryBuilder};
+use time::OffsetDateTime;
+
+/// 列表可搜尋、篩選與排序的欄位（欄位名稱會直接組進SQL，只能使用固定的字串）
+pub struct ListSpec {
+    /// 資料表（含schema）
+    pub table: &'static str,
+    /// 主鍵，排序欄位相同時再依主鍵排序，確保分頁結果穩定
+    pub primary_key: &'static str,
+    /// search比對的欄位（不分大小寫的部分比對，任一欄位符合即可）
+    pub search_columns: &'static [&'static str],
+    /// 可排序的欄位，第一個為預設排序
+    pub sort_columns: &'static [&'static str],
+    /// from、to篩選的時間欄位
+    pub date_column: &'static str,
+}
+
+/// 排序方向
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        }
+    }
+}
+
+/// 列表的搜尋、日期篩選與排序條件（皆為選填），時間格式為RFC 3339（from包含、to不包含）
+#[derive(Deserialize, Default)]
+pub struct ListParams {
+    pub search: Option<String>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub to: Option<OffsetDateTime>,
+    pub sort: Option<String>,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+/// 帳號列表（管理員、用戶）共用的篩選條件
+#[derive(Deserialize, Default)]
+pub struct AccountFilter {
+    pub account_status: Option<bool>,
+    pub account_rule: Option<i32>,
+}
+
+/// 等值篩選的值
+pub enum FilterValue {
+    Bool(bool),
+    Int(i32),
+    Text(String),
+}
+
+impl From<bool> for FilterValue {
+    fn from(value: bool) -> Self {
+        FilterValue::Bool(value)
+    }
+}
+
+impl From<i32> for FilterValue {
+    fn from(value: i32) -> Self {
+        FilterValue::Int(value)
+    }
+}
+
+impl From<String> for FilterValue {
+    fn from(value: String) -> Self {
+        FilterValue::Text(value)
+    }
+}
+
+/// 依ListSpec組出分頁查詢（條件皆以參數綁定），用法：
+/// `ListQuery::new(&SPEC, params).filter_eq("account_status", filter.account_status).fetch_page::<Row>(db, &pagination)`
+pub struct ListQuery<'a> {
+    spec: &'a ListSpec,
+    params: ListParams,
+    filters: Vec<(&'static str, FilterValue)>,
+}
+
+impl<'a> ListQuery<'a> {
+    pub fn new(spec: &'a ListSpec, params: ListParams) -> Self {
+        ListQuery {
+            spec,
+            params,
+            filters: Vec::new(),
+        }
+    }
+
+    /// 加上等值篩選，value為None時不篩選
+    pub fn filter_eq<V: Into<FilterValue>>(
+        mut self,
+        column: &'static str,
+        value: Option<V>,
+    ) -> Self {
+        if let Some(value) = value {
+            self.filters.push((column, value.into()));
+        }
+        self
+    }
+
+    /// 加上帳號列表共用的account_status、account_rule篩選
+    pub fn account_filter(self, filter: AccountFilter) -> Self {
+        self.filter_eq("account_status", filter.account_status)
+            .filter_eq("account_rule", filter.account_rule)
+    }
+
+    /// 查詢一頁資料與總頁數（無提供分頁參數則使用預設值），排序欄位不在ListSpec.sort_columns內時回傳422
+    pub async fn fetch_page<T>(
+        &self,
+        db: &Pool<Postgres>,
+        pagination: &PaginationParams,
+    ) -> Result<PaginationResp, (StatusCode, Json<Value>)>
+    where
+        T: for<'r> FromRow<'r, PgRow> + Serialize + Send + Unpin,
+    {
+        let page = pagination.page.unwrap_or(1);
+        let count = pagination.count.unwrap_or(5);
+        let mut page_query = self.page_query(page, count)?;
+
+        let (total,): (i64,) = self
+            .count_query()
+            .build_query_as()
+            .fetch_one(db)
+            .await
+            .map_err(get_db_err)?;
+        let result: Vec<T> = page_query
+            .build_query_as()
+            .fetch_all(db)
+            .await
+            .map_err(get_db_err)?;
+        Ok(PaginationResp::new(
+            total as u64,
+            count,
+            page,
+            json!(result),
+        ))
+    }
+
+    /// 符合條件的總筆數
+    fn count_query(&self) -> QueryBuilder<'static, Postgres> {
+        let mut builder = QueryBuilder::new(format!("select count(*) from {}", self.spec.table));
+        self.push_where(&mut builder);
+        builder
+    }
+
+    /// 一頁資料（排序欄位不在白名單內時回傳422）
+    fn page_query(
+        &self,
+        page: u64,
+        count: u64,
+    ) -> Result<QueryBuilder<'static, Postgres>, (StatusCode, Json<Value>)> {
+        let order_by = self.order_by()?;
+        let mut builder = QueryBuilder::new(format!("select * from {}", self.spec.table));
+        self.push_where(&mut builder);
+        builder
+            .push(order_by)
+            .push(" limit ")
+            .push_bind(count as i64)
+            .push(" offset ")
+            .push_bind(pagination_offset(page, count) as i64);
+        Ok(builder)
+    }
+
+    fn order_by(&self) -> Result<String, (StatusCode, Json<Value>)> {
+        let sort = match &self.params.sort {
+            Some(sort) => self
+                .spec
+                .sort_columns
+                .iter()
+                .find(|column| *column == sort)
+                .ok_or_else(|| {
+                    err_json_gen(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        Some(format!(
+                            "Unable to sort by \"{}\". Sortable columns: {}.",
+                            sort,
+                            self.spec.sort_columns.join(", ")
+                        )),
+                    )
+                })?,
+            None => &self.spec.sort_columns[0],
+        };
+        let order = self.params.order.as_sql();
+        Ok(if *sort == self.spec.primary_key {
+            format!(" order by {} {}", sort, order)
+        } else {
+            format!(
+                " order by {} {}, {} {}",
+                sort, order, self.spec.primary_key, order
+            )
+        })
+    }
+
+    fn push_where(&self, builder: &mut QueryBuilder<'static, Postgres>) {
+        builder.push(" where true");
+        if let Some(search) = self.params.search.as_deref().map(str::trim) {
+            if !search.is_empty() && !self.spec.search_columns.is_empty() {
+                let pattern = like_pattern(search);
+                builder.push(" and (");
+                let mut columns = builder.separated(" or ");
+                for column in self.spec.search_columns {
+                    columns
+                        .push(format!("{} ilike ", column))
+                        .push_bind_unseparated(pattern.clone());
+                }
+                builder.push(")");
+            }
+        }
+        for (column, value) in &self.filters {
+            builder.push(format!(" and {} = ", column));
+            match value {
+                FilterValue::Bool(value) => builder.push_bind(*value),
+                FilterValue::Int(value) => builder.push_bind(*value),
+                FilterValue::Text(value) => builder.push_bind(value.clone()),
+            };
+        }
+        if let Some(from) = self.params.from {
+            builder
+                .push(format!(" and {} >= ", self.spec.date_column))
+                .push_bind(from);
+        }
+        if let Some(to) = self.params.to {
+            builder
+                .push(format!(" and {} < ", self.spec.date_column))
+                .push_bind(to);
+        }
+    }
+}
+
+/// 部分比對使用的LIKE樣式（跳脫`\`、`%`與`_`，搜尋字串只會被當成一般文字）
+fn like_pattern(search: &str) -> String {
+    format!(
+        "%{}%",
+        search
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: ListSpec = ListSpec {
+        table: "backendmodulesdb.admin_info",
+        primary_key: "uuid",
+        search_columns: &["login_account", "user_name"],
+        sort_columns: &["creation_timestamp", "user_name", "uuid"],
+        date_column: "creation_timestamp",
+    };
+
+    fn params(sort: Option<&str>, order: SortOrder) -> ListParams {
+        ListParams {
+            sort: sort.map(str::to_string),
+            order,
+            ..ListParams::default()
+        }
+    }
+
+    #[test]
+    fn default_sort_uses_first_column_with_primary_key_tie_break() {
+        let query = ListQuery::new(&SPEC, ListParams::default());
+        assert_eq!(
+            query.page_query(1, 5).unwrap().sql(),
+            "select * from backendmodulesdb.admin_info where true order by creation_timestamp asc, uuid asc limit $1 offset $2"
+        );
+    }
+
+    #[test]
+    fn sort_by_whitelisted_column_in_either_direction() {
+        let query = ListQuery::new(&SPEC, params(Some("user_name"), SortOrder::Desc));
+        assert!(query
+            .page_query(2, 10)
+            .unwrap()
+            .sql()
+            .ends_with(" order by user_name desc, uuid desc limit $1 offset $2"));
+        // 依主鍵排序時不需要再加上主鍵
+        let query = ListQuery::new(&SPEC, params(Some("uuid"), SortOrder::Asc));
+        assert!(query
+            .page_query(1, 5)
+            .unwrap()
+            .sql()
+            .ends_with(" order by uuid asc limit $1 offset $2"));
+    }
+
+    #[test]
+    fn sort_by_unknown_column_is_rejected() {
+        for sort in ["login_password", "user_name; drop table admin_info", ""] {
+            let query = ListQuery::new(&SPEC, params(Some(sort), SortOrder::Asc));
+            let (status, _) = query.page_query(1, 5).err().unwrap();
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    #[test]
+    fn search_matches_every_search_column_with_one_group() {
+        let query = ListQuery::new(
+            &SPEC,
+            ListParams {
+                search: Some("  ming ".to_string()),
+                ..ListParams::default()
+            },
+        );
+        assert_eq!(
+            query.count_query().sql(),
+            "select count(*) from backendmodulesdb.admin_info where true and (login_account ilike $1 or user_name ilike $2)"
+        );
+        // 空白的搜尋字串不加條件
+        let query = ListQuery::new(
+            &SPEC,
+            ListParams {
+                search: Some("   ".to_string()),
+                ..ListParams::default()
+            },
+        );
+        assert_eq!(
+            query.count_query().sql(),
+            "select count(*) from backendmodulesdb.admin_info where true"
+        );
+    }
+
+    #[test]
+    fn like_pattern_escapes_wildcards() {
+        assert_eq!(like_pattern("ming"), "%ming%");
+        assert_eq!(like_pattern("100%"), "%100\\%%");
+        assert_eq!(like_pattern("a_b"), "%a\\_b%");
+        assert_eq!(like_pattern("c:\\dir"), "%c:\\\\dir%");
+        assert_eq!(like_pattern("\\%_"), "%\\\\\\%\\_%");
+    }
+
+    #[test]
+    fn filters_and_date_bounds_are_bound_parameters() {
+        let query = ListQuery::new(
+            &SPEC,
+            ListParams {
+                search: Some("ming".to_string()),
+                from: Some(OffsetDateTime::UNIX_EPOCH),
+                to: Some(OffsetDateTime::UNIX_EPOCH + time::Duration::days(1)),
+                ..ListParams::default()
+            },
+        )
+        .account_filter(AccountFilter {
+            account_status: Some(true),
+            account_rule: None,
+        })
+        .filter_eq("login_account", Some("root".to_string()));
+        assert_eq!(
+            query.page_query(1, 5).unwrap().sql(),
+            "select * from backendmodulesdb.admin_info where true \
+             and (login_account ilike $1 or user_name ilike $2) \
+             and account_status = $3 and login_account = $4 \
+             and creation_timestamp >= $5 and creation_timestamp < $6 \
+             order by creation_timestamp asc, uuid asc limit $7 offset $8"
+        );
+    }
+}
@@ -0,0 +1,58 @@
+use crate::config::config;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Client, Method, Request};
+use serde_json::{json, Value};
+
+/// 透過notification服務寄送範本郵件（以account.notification_api_key驗證），回傳notification服務的錯誤原因
+///
+/// 未設定API Key時不寄送，只記錄警告
+pub async fn send_template_mail(
+    to_address: &str,
+    template: &str,
+    variables: Value,
+) -> Result<(), String> {
+    let account = &config().account;
+    let api_key = match &account.notification_api_key {
+        Some(api_key) => api_key,
+        None => {
+            tracing::warn!(
+                "account.notification_api_key is not set, skipped sending {} to {}.",
+                template,
+                to_address
+            );
+            return Ok(());
+        }
+    };
+    let body = json!({
+        "from_address": account.mail_from,
+        "to_address": to_address,
+        "template": template,
+        "variables": variables
+    });
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!(
+            "{}/notification/sentMail",
+            account.notification_url.trim_end_matches('/')
+        ))
+        .header(CONTENT_TYPE, "application/json")
+        .header("x-api-key", api_key)
+        .body(Body::from(body.to_string()))
+        .map_err(|error| error.to_string())?;
+    let response = Client::new()
+        .request(request)
+        .await
+        .map_err(|error| format!("Unable to reach the notification service: {}", error))?;
+    if response.status().is_success() {
+        return Ok(());
+    }
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .unwrap_or_default();
+    Err(format!(
+        "The notification service responded {}: {}",
+        status,
+        String::from_utf8_lossy(&body)
+    ))
+}
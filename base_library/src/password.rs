@@ -0,0 +1,186 @@
+use crate::config::config;
+use crate::err_json_gen;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use axum::http::StatusCode;
+use axum::Json;
+use serde_json::Value;
+use subtle::ConstantTimeEq;
+
+/// 密碼比對結果
+#[derive(Debug, PartialEq, Eq)]
+pub enum PasswordCheck {
+    /// 密碼正確，且資料庫內已是目前參數的Argon2id雜湊
+    Matched,
+    /// 密碼正確，但資料庫內仍是明文或舊參數，需要重新雜湊後寫回
+    NeedsRehash,
+    /// 密碼錯誤
+    Mismatched,
+}
+
+fn hasher() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::default())
+}
+
+/// 檢查新密碼是否符合password設定的規則，不符合時回傳422與所有未符合的規則
+pub fn check_password_policy(password: &str) -> Result<(), (StatusCode, Json<Value>)> {
+    let policy = &config().password;
+    let length = password.chars().count();
+    let mut violations = Vec::new();
+    if length < policy.min_length {
+        violations.push(format!("be at least {} characters long", policy.min_length));
+    }
+    if length > policy.max_length {
+        violations.push(format!("be at most {} characters long", policy.max_length));
+    }
+    if policy.require_lowercase && !password.chars().any(|c| c.is_ascii_lowercase()) {
+        violations.push("contain a lowercase letter".to_string());
+    }
+    if policy.require_uppercase && !password.chars().any(|c| c.is_ascii_uppercase()) {
+        violations.push("contain an uppercase letter".to_string());
+    }
+    if policy.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+        violations.push("contain a digit".to_string());
+    }
+    if policy.require_symbol && password.chars().all(|c| c.is_ascii_alphanumeric()) {
+        violations.push("contain a symbol".to_string());
+    }
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(err_json_gen(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some(format!("Password must {}.", violations.join(", "))),
+        ))
+    }
+}
+
+/// 以Argon2id雜湊密碼，回傳PHC字串（包含演算法、參數與鹽值，可直接存入資料庫）
+pub fn hash_password(password: &str) -> Result<String, (StatusCode, Json<Value>)> {
+    let salt = SaltString::generate(&mut OsRng);
+    hasher()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|error| {
+            err_json_gen(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Some(format!("Unable to hash password: {}", error)),
+            )
+        })
+}
+
+/// 比對密碼與資料庫內的值，無法解析為PHC字串的值視為舊版明文密碼（皆以常數時間比較）
+pub fn verify_password(password: &str, stored: &str) -> PasswordCheck {
+    match PasswordHash::new(stored) {
+        Ok(hash) => {
+            if hasher()
+                .verify_password(password.as_bytes(), &hash)
+                .is_err()
+            {
+                return PasswordCheck::Mismatched;
+            }
+            let default_params = Params::default();
+            let up_to_date = hash.algorithm == Algorithm::Argon2id.ident()
+                && hash.version == Some(Version::V0x13.into())
+                && Params::try_from(&hash)
+                    .map(|params| {
+                        params.m_cost() == default_params.m_cost()
+                            && params.t_cost() == default_params.t_cost()
+                            && params.p_cost() == default_params.p_cost()
+                    })
+                    .unwrap_or(false);
+            if up_to_date {
+                PasswordCheck::Matched
+            } else {
+                PasswordCheck::NeedsRehash
+            }
+        }
+        Err(_) => {
+            if bool::from(password.as_bytes().ct_eq(stored.as_bytes())) {
+                PasswordCheck::NeedsRehash
+            } else {
+                PasswordCheck::Mismatched
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_password_round_trip() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert_ne!(hash, hash_password("correct horse").unwrap());
+        assert_eq!(
+            verify_password("correct horse", &hash),
+            PasswordCheck::Matched
+        );
+    }
+
+    #[test]
+    fn verify_password_mismatched() {
+        let hash = hash_password("correct horse").unwrap();
+        assert_eq!(
+            verify_password("battery staple", &hash),
+            PasswordCheck::Mismatched
+        );
+        assert_eq!(verify_password("", &hash), PasswordCheck::Mismatched);
+    }
+
+    #[test]
+    fn verify_password_outdated_params_needs_rehash() {
+        let params = Params::new(8 * 1024, 1, 1, None).unwrap();
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password(b"correct horse", &salt)
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            verify_password("correct horse", &hash),
+            PasswordCheck::NeedsRehash
+        );
+        assert_eq!(
+            verify_password("battery staple", &hash),
+            PasswordCheck::Mismatched
+        );
+    }
+
+    #[test]
+    fn verify_password_other_algorithm_needs_rehash() {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::new(Algorithm::Argon2i, Version::V0x13, Params::default())
+            .hash_password(b"correct horse", &salt)
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            verify_password("correct horse", &hash),
+            PasswordCheck::NeedsRehash
+        );
+    }
+
+    #[test]
+    fn legacy_plaintext_upgrades_to_argon2id() {
+        assert_eq!(
+            verify_password("legacy-password", "legacy-password"),
+            PasswordCheck::NeedsRehash
+        );
+        assert_eq!(
+            verify_password("legacy-passwore", "legacy-password"),
+            PasswordCheck::Mismatched
+        );
+        assert_eq!(
+            verify_password("legacy", "legacy-password"),
+            PasswordCheck::Mismatched
+        );
+        // 登入成功後寫回的雜湊值之後即為Matched
+        let rehashed = hash_password("legacy-password").unwrap();
+        assert_eq!(
+            verify_password("legacy-password", &rehashed),
+            PasswordCheck::Matched
+        );
+    }
+}
@@ -0,0 +1,532 @@
+use crate::password::{check_password_policy, hash_password};
+use crate::token::{revoke_account_sessions, TokenOwner};
+use crate::{err_json_gen, get_db_err, now_local_time};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use sqlx::postgres::PgRow;
+use sqlx::types::Uuid;
+use sqlx::{FromRow, Pool, Postgres};
+use time::format_description::well_known::Iso8601;
+use time::OffsetDateTime;
+
+/// 帳號資料表（管理員、用戶）可部分更新的欄位（欄位名稱會直接組進SQL，只能使用固定的字串）
+pub struct AccountPatchSpec {
+    /// 資料表（含schema），主鍵為uuid
+    pub table: &'static str,
+    /// 變更密碼後撤銷登入使用的Token持有者類型
+    pub owner: TokenOwner,
+    /// 可修改的欄位（login_password另外處理）
+    pub columns: &'static [&'static str],
+}
+
+/// 不可修改的欄位
+const IMMUTABLE_FIELDS: [&str; 2] = ["uuid", "creation_timestamp"];
+
+/// 檢查並套用Merge Patch後的結果
+struct PreparedPatch<T> {
+    /// 用戶端取得資料時的update_timestamp
+    update_timestamp: OffsetDateTime,
+    /// 套用後的資料
+    record: T,
+    /// 有提供時的新密碼（明文，尚未檢查規則）
+    login_password: Option<String>,
+}
+
+/// 依照JSON Merge Patch部分更新帳號（只更新有提供的欄位；login_password為null或未提供時不變更，變更時撤銷該帳號所有的登入），
+/// 需附上查詢時取得的update_timestamp（未提供時回傳428），資料已被其他請求修改時回傳409，
+/// 修改uuid或creation_timestamp、內容不符合型別時回傳422
+pub async fn patch_account<T>(
+    db: &Pool<Postgres>,
+    spec: &AccountPatchSpec,
+    uuid: Uuid,
+    patch: Value,
+) -> Result<Value, (StatusCode, Json<Value>)>
+where
+    T: Serialize + DeserializeOwned + for<'r> FromRow<'r, PgRow> + Send + Unpin,
+{
+    let current: T = sqlx::query_as(&format!("select * from {} where uuid = $1", spec.table))
+        .bind(uuid)
+        .fetch_one(db)
+        .await
+        .map_err(get_db_err)?;
+    let prepared = prepare_patch(&current, patch)?;
+    let record = serde_json::to_value(&prepared.record).map_err(|error| {
+        err_json_gen(StatusCode::INTERNAL_SERVER_ERROR, Some(error.to_string()))
+    })?;
+    let record = updatable_fields(spec, record);
+    if let Some(login_account) = record.get("login_account").and_then(Value::as_str) {
+        let (count,): (i64,) = sqlx::query_as(&format!(
+            "select count(*) from {} where login_account = $1 and uuid <> $2",
+            spec.table
+        ))
+        .bind(login_account)
+        .bind(uuid)
+        .fetch_one(db)
+        .await
+        .map_err(get_db_err)?;
+        if count > 0 {
+            return Err(err_json_gen(
+                StatusCode::CONFLICT,
+                Some("Account with same name existed, please specify another name.".to_string()),
+            ));
+        }
+    }
+    let login_password = match &prepared.login_password {
+        Some(login_password) => {
+            check_password_policy(login_password)?;
+            Some(hash_password(login_password)?)
+        }
+        None => None,
+    };
+    let updated: Option<T> = sqlx::query_as(&update_sql(spec))
+        .bind(uuid)
+        .bind(record)
+        .bind(login_password.as_ref())
+        .bind(now_local_time())
+        .bind(prepared.update_timestamp)
+        .fetch_optional(db)
+        .await
+        .map_err(get_db_err)?;
+    let updated = updated.ok_or_else(patch_conflict)?;
+    if login_password.is_some() {
+        revoke_account_sessions(db, spec.owner, uuid).await?;
+    }
+    Ok(json!(updated))
+}
+
+/// 只保留可修改的欄位（時間等欄位的序列化格式不一定能被jsonb_populate_record解析）
+fn updatable_fields(spec: &AccountPatchSpec, record: Value) -> Value {
+    match record {
+        Value::Object(mut record) => {
+            record.retain(|key, _| spec.columns.contains(&key.as_str()));
+            Value::Object(record)
+        }
+        record => record,
+    }
+}
+
+/// 以jsonb_populate_record把套用後的資料轉成資料表的型別，只更新spec.columns；
+/// $1 uuid、$2 資料、$3 新密碼雜湊、$4 新的update_timestamp、$5 用戶端的update_timestamp
+fn update_sql(spec: &AccountPatchSpec) -> String {
+    let columns: Vec<String> = spec
+        .columns
+        .iter()
+        .map(|column| format!("{0} = patched.{0}", column))
+        .collect();
+    format!(
+        "update {0} as account set {1}, login_password = coalesce($3, account.login_password), update_timestamp = $4 \
+         from jsonb_populate_record(null::{0}, $2) as patched \
+         where account.uuid = $1 and account.update_timestamp = $5 returning account.*",
+        spec.table,
+        columns.join(", ")
+    )
+}
+
+/// 取出update_timestamp、拒絕修改不可修改的欄位，並把patch套用到目前的資料上
+fn prepare_patch<T>(
+    current: &T,
+    mut patch: Value,
+) -> Result<PreparedPatch<T>, (StatusCode, Json<Value>)>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let update_timestamp = take_update_timestamp(&mut patch)?;
+    if let Some(field) = IMMUTABLE_FIELDS
+        .iter()
+        .find(|field| patch.get(**field).is_some())
+    {
+        return Err(err_json_gen(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some(format!("{} can't be modified.", field)),
+        ));
+    }
+    let mut value = serde_json::to_value(current).map_err(|error| {
+        err_json_gen(StatusCode::INTERNAL_SERVER_ERROR, Some(error.to_string()))
+    })?;
+    merge_patch(&mut value, &patch);
+    let login_password = value
+        .get("login_password")
+        .and_then(Value::as_str)
+        .filter(|login_password| !login_password.is_empty())
+        .map(str::to_string);
+    let record = serde_json::from_value(value)
+        .map_err(|error| err_json_gen(StatusCode::UNPROCESSABLE_ENTITY, Some(error.to_string())))?;
+    Ok(PreparedPatch {
+        update_timestamp,
+        record,
+        login_password,
+    })
+}
+
+/// 依照JSON Merge Patch（RFC 7396）修改target：null代表移除欄位，物件遞迴合併，其他值直接取代
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        _ => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let target = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// 取出Merge Patch內的update_timestamp（用戶端取得資料時的值，ISO 8601），作為樂觀鎖的比對條件；
+/// patch不是物件時回傳422，缺少update_timestamp時回傳428
+pub fn take_update_timestamp(
+    patch: &mut Value,
+) -> Result<OffsetDateTime, (StatusCode, Json<Value>)> {
+    let patch = patch.as_object_mut().ok_or_else(|| {
+        err_json_gen(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some("Merge patch must be a JSON object.".to_string()),
+        )
+    })?;
+    match patch.remove("update_timestamp") {
+        Some(Value::String(update_timestamp)) => {
+            OffsetDateTime::parse(&update_timestamp, &Iso8601::DEFAULT).map_err(|error| {
+                err_json_gen(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Some(format!("update_timestamp is invalid: {}", error)),
+                )
+            })
+        }
+        _ => Err(err_json_gen(
+            StatusCode::PRECONDITION_REQUIRED,
+            Some(
+                "update_timestamp from the latest query is required to prevent overwriting concurrent changes."
+                    .to_string(),
+            ),
+        )),
+    }
+}
+
+/// 更新時update_timestamp不相符（資料已被其他請求修改或移除）的錯誤
+pub fn patch_conflict() -> (StatusCode, Json<Value>) {
+    err_json_gen(
+        StatusCode::CONFLICT,
+        Some(
+            "The record has been modified or removed since it was fetched. Please query it again and retry."
+                .to_string(),
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use sqlx::postgres::PgPoolOptions;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, sqlx::FromRow)]
+    struct TestAccount {
+        uuid: Uuid,
+        login_account: String,
+        #[serde(default, skip_serializing)]
+        login_password: String,
+        account_status: bool,
+        user_name: String,
+        user_email: Option<String>,
+        #[serde(with = "time::serde::iso8601")]
+        creation_timestamp: OffsetDateTime,
+        #[serde(with = "time::serde::iso8601")]
+        update_timestamp: OffsetDateTime,
+    }
+
+    fn account() -> TestAccount {
+        TestAccount {
+            uuid: Uuid::nil(),
+            login_account: "ming".to_string(),
+            login_password: "$argon2id$hash".to_string(),
+            account_status: true,
+            user_name: "Ming".to_string(),
+            user_email: Some("ming@example.com".to_string()),
+            creation_timestamp: OffsetDateTime::UNIX_EPOCH,
+            update_timestamp: OffsetDateTime::UNIX_EPOCH,
+        }
+    }
+
+    fn patched(target: Value, patch: Value) -> Value {
+        let mut target = target;
+        merge_patch(&mut target, &patch);
+        target
+    }
+
+    #[test]
+    fn merge_patch_rfc7396_examples() {
+        let cases = [
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (
+                json!({"a": "b"}),
+                json!({"b": "c"}),
+                json!({"a": "b", "b": "c"}),
+            ),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (
+                json!({"a": "b", "b": "c"}),
+                json!({"a": null}),
+                json!({"b": "c"}),
+            ),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+            (
+                json!({"a": {"b": "c"}}),
+                json!({"a": {"b": "d", "c": null}}),
+                json!({"a": {"b": "d"}}),
+            ),
+            (
+                json!({"a": [{"b": "c"}]}),
+                json!({"a": [1]}),
+                json!({"a": [1]}),
+            ),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+            (json!({"a": "foo"}), json!(null), json!(null)),
+            (json!({"a": "foo"}), json!("bar"), json!("bar")),
+            (
+                json!({"e": null}),
+                json!({"a": 1}),
+                json!({"e": null, "a": 1}),
+            ),
+            (
+                json!([1, 2]),
+                json!({"a": "b", "c": null}),
+                json!({"a": "b"}),
+            ),
+            (
+                json!({}),
+                json!({"a": {"bb": {"ccc": null}}}),
+                json!({"a": {"bb": {}}}),
+            ),
+        ];
+        for (target, patch, expected) in cases {
+            assert_eq!(
+                patched(target.clone(), patch.clone()),
+                expected,
+                "{} + {}",
+                target,
+                patch
+            );
+        }
+    }
+
+    #[test]
+    fn prepare_patch_updates_only_sent_fields() {
+        let prepared = prepare_patch(
+            &account(),
+            json!({"update_timestamp": "1970-01-01T00:00:00Z", "account_status": false, "user_email": null}),
+        )
+        .unwrap();
+        assert_eq!(prepared.update_timestamp, OffsetDateTime::UNIX_EPOCH);
+        assert_eq!(prepared.login_password, None);
+        assert_eq!(
+            prepared.record,
+            TestAccount {
+                account_status: false,
+                user_email: None,
+                // 密碼不會被序列化，套用後為空字串，更新時保留原本的值
+                login_password: String::new(),
+                ..account()
+            }
+        );
+    }
+
+    #[test]
+    fn prepare_patch_reads_new_password() {
+        let prepared = prepare_patch(
+            &account(),
+            json!({"update_timestamp": "1970-01-01T00:00:00Z", "login_password": "new password"}),
+        )
+        .unwrap();
+        assert_eq!(prepared.login_password.as_deref(), Some("new password"));
+        for login_password in [json!(null), json!("")] {
+            let prepared = prepare_patch(
+                &account(),
+                json!({"update_timestamp": "1970-01-01T00:00:00Z", "login_password": login_password}),
+            )
+            .unwrap();
+            assert_eq!(prepared.login_password, None);
+        }
+    }
+
+    #[test]
+    fn prepare_patch_rejects_immutable_fields() {
+        for patch in [
+            json!({"update_timestamp": "1970-01-01T00:00:00Z", "uuid": Uuid::from_u128(1)}),
+            json!({"update_timestamp": "1970-01-01T00:00:00Z", "uuid": null}),
+            json!({"update_timestamp": "1970-01-01T00:00:00Z", "creation_timestamp": "2020-01-01T00:00:00Z"}),
+        ] {
+            let (status, _) = prepare_patch(&account(), patch).err().unwrap();
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    #[test]
+    fn prepare_patch_rejects_invalid_types() {
+        let (status, _) = prepare_patch(
+            &account(),
+            json!({"update_timestamp": "1970-01-01T00:00:00Z", "account_status": "yes"}),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, _) = prepare_patch(&account(), json!(["not", "an", "object"]))
+            .err()
+            .unwrap();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[test]
+    fn missing_update_timestamp_requires_precondition() {
+        let (status, _) = prepare_patch(&account(), json!({"account_status": false}))
+            .err()
+            .unwrap();
+        assert_eq!(status, StatusCode::PRECONDITION_REQUIRED);
+        let (status, _) = prepare_patch(&account(), json!({"update_timestamp": null}))
+            .err()
+            .unwrap();
+        assert_eq!(status, StatusCode::PRECONDITION_REQUIRED);
+        let (status, _) = prepare_patch(&account(), json!({"update_timestamp": "yesterday"}))
+            .err()
+            .unwrap();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[test]
+    fn only_updatable_fields_are_sent_to_database() {
+        let record = serde_json::to_value(account()).unwrap();
+        assert_eq!(
+            updatable_fields(&TEST_SPEC, record),
+            json!({
+                "login_account": "ming",
+                "account_status": true,
+                "user_name": "Ming",
+                "user_email": "ming@example.com"
+            })
+        );
+    }
+
+    #[test]
+    fn update_sql_checks_update_timestamp() {
+        assert_eq!(
+            update_sql(&TEST_SPEC),
+            "update pg_temp.patch_account as account set login_account = patched.login_account, \
+             account_status = patched.account_status, user_name = patched.user_name, \
+             user_email = patched.user_email, login_password = coalesce($3, account.login_password), \
+             update_timestamp = $4 from jsonb_populate_record(null::pg_temp.patch_account, $2) as patched \
+             where account.uuid = $1 and account.update_timestamp = $5 returning account.*"
+        );
+        let (status, _) = patch_conflict();
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    const TEST_SPEC: AccountPatchSpec = AccountPatchSpec {
+        table: "pg_temp.patch_account",
+        owner: TokenOwner::User,
+        columns: &["login_account", "account_status", "user_name", "user_email"],
+    };
+
+    /// 需要PostgreSQL，未設定DATABASE_URL時略過
+    #[tokio::test]
+    async fn patch_account_detects_concurrent_changes() {
+        let url = match std::env::var("DATABASE_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                eprintln!("DATABASE_URL is not set, skipped.");
+                return;
+            }
+        };
+        // 暫存資料表只存在於同一個連線
+        let db = PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&url)
+            .await
+            .unwrap();
+        sqlx::query(
+            r#"
+            create temp table patch_account (
+                uuid uuid primary key,
+                login_account varchar not null,
+                login_password varchar not null,
+                account_status boolean not null,
+                user_name varchar not null,
+                user_email varchar,
+                creation_timestamp timestamptz not null,
+                update_timestamp timestamptz not null
+            )
+            "#,
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        let uuid = Uuid::from_u128(1);
+        let fetched = OffsetDateTime::now_utc().replace_nanosecond(0).unwrap();
+        sqlx::query("insert into pg_temp.patch_account values ($1, 'ming', 'hash', true, 'Ming', 'ming@example.com', $2, $2)")
+            .bind(uuid)
+            .bind(fetched)
+            .execute(&db)
+            .await
+            .unwrap();
+        let update_timestamp = fetched.format(&Iso8601::DEFAULT).unwrap();
+
+        let updated = patch_account::<TestAccount>(
+            &db,
+            &TEST_SPEC,
+            uuid,
+            json!({"update_timestamp": update_timestamp, "account_status": false}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated["account_status"], json!(false));
+        assert_eq!(updated["user_name"], json!("Ming"));
+        assert_eq!(updated["user_email"], json!("ming@example.com"));
+        assert!(updated.get("login_password").is_none());
+
+        // 使用舊的update_timestamp再次更新時回傳409，資料不變
+        let (status, _) = patch_account::<TestAccount>(
+            &db,
+            &TEST_SPEC,
+            uuid,
+            json!({"update_timestamp": update_timestamp, "user_name": "Stale"}),
+        )
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // 使用最新的update_timestamp則可以更新
+        let updated = patch_account::<TestAccount>(
+            &db,
+            &TEST_SPEC,
+            uuid,
+            json!({"update_timestamp": updated["update_timestamp"], "user_email": null}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated["user_email"], json!(null));
+        assert_eq!(updated["account_status"], json!(false));
+
+        let (status, _) = patch_account::<TestAccount>(
+            &db,
+            &TEST_SPEC,
+            Uuid::from_u128(2),
+            json!({"update_timestamp": update_timestamp}),
+        )
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
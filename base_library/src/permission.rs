@@ -0,0 +1,151 @@
+use crate::{err_json_gen, get_db_err, AdminToken, Claims};
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::{async_trait, Extension, Json};
+use serde_json::Value;
+use sqlx::types::Uuid;
+use sqlx::{Pool, Postgres};
+use std::marker::PhantomData;
+
+/// 權限（每個權限是一個型別，名稱存放於角色的permissions欄位）
+pub trait Permission: Send + Sync + 'static {
+    const NAME: &'static str;
+}
+
+macro_rules! permissions {
+    ($($ident:ident => $name:literal),* $(,)?) => {
+        $(
+            pub struct $ident;
+
+            impl Permission for $ident {
+                const NAME: &'static str = $name;
+            }
+        )*
+
+        /// 所有可指派給角色的權限名稱
+        pub const ALL_PERMISSIONS: &[&str] = &[$($name),*];
+    };
+}
+
+permissions! {
+    AdminRead => "admin.read",
+    AdminWrite => "admin.write",
+    AdminDelete => "admin.delete",
+    UserRead => "user.read",
+    UserWrite => "user.write",
+    UserDelete => "user.delete",
+    RoleRead => "role.read",
+    RoleWrite => "role.write",
+    RoleDelete => "role.delete",
+    NotificationRead => "notification.read",
+    TemplateRead => "template.read",
+    TemplateWrite => "template.write",
+    TemplateDelete => "template.delete",
+    TopicWrite => "topic.write",
+    ApiKeyRead => "api_key.read",
+    ApiKeyWrite => "api_key.write",
+    ApiKeyDelete => "api_key.delete",
+}
+
+/// 最高權限角色（既有管理員預設的account_rule），必須擁有所有權限且不可刪除
+pub const SUPERUSER_ROLE: i32 = 0;
+
+/// 檢查管理員的角色（admin_info.account_rule）是否擁有權限P，沒有則回傳403
+///
+/// 用法與AdminToken相同：`RequirePermission(admin_token, _): RequirePermission<UserDelete>`
+pub struct RequirePermission<P: Permission>(pub Claims, pub PhantomData<P>);
+
+#[async_trait]
+impl<S, P> FromRequestParts<S> for RequirePermission<P>
+where
+    S: Send + Sync,
+    P: Permission,
+{
+    type Rejection = (StatusCode, Json<Value>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let AdminToken(claims) = AdminToken::from_request_parts(parts, state).await?;
+        let Extension(db) = Extension::<Pool<Postgres>>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| {
+                err_json_gen(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Some("Role information is unavailable on this server.".to_string()),
+                )
+            })?;
+        if !has_permission::<P>(&db, claims.uuid)
+            .await
+            .map_err(get_db_err)?
+        {
+            Err(err_json_gen(
+                StatusCode::FORBIDDEN,
+                Some(format!(
+                    "Your role doesn't have the \"{}\" permission required by this API.",
+                    P::NAME
+                )),
+            ))
+        } else {
+            Ok(Self(claims, PhantomData))
+        }
+    }
+}
+
+/// 管理員（需為啟用狀態）的角色是否擁有權限P
+pub async fn has_permission<P: Permission>(
+    db: &Pool<Postgres>,
+    uuid: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        select count(*) from backendmodulesdb.admin_info admin_info
+        join backendmodulesdb.role role on role.id = admin_info.account_rule
+        where admin_info.uuid = $1
+            and admin_info.account_status = true
+            and $2 = any(role.permissions);
+        "#,
+        uuid,
+        P::NAME
+    )
+    .fetch_one(db)
+    .await?;
+    Ok(record.count.unwrap_or(0) > 0)
+}
+
+/// 檢查管理員（caller）是否可以將帳號的角色（account_rule）設為role：需要role.write權限，
+/// 指派最高權限角色時本身也必須是最高權限角色，否則回傳403
+pub async fn check_role_assignment(
+    db: &Pool<Postgres>,
+    caller: Uuid,
+    role: i32,
+) -> Result<(), (StatusCode, Json<Value>)> {
+    if !has_permission::<RoleWrite>(db, caller)
+        .await
+        .map_err(get_db_err)?
+    {
+        return Err(err_json_gen(
+            StatusCode::FORBIDDEN,
+            Some(format!(
+                "Changing account_rule requires the \"{}\" permission.",
+                RoleWrite::NAME
+            )),
+        ));
+    }
+    if role == SUPERUSER_ROLE {
+        let caller_role = sqlx::query!(
+            "select account_rule from backendmodulesdb.admin_info where uuid = $1;",
+            caller
+        )
+        .fetch_optional(db)
+        .await
+        .map_err(get_db_err)?
+        .map(|record| record.account_rule);
+        if caller_role != Some(SUPERUSER_ROLE) {
+            return Err(err_json_gen(
+                StatusCode::FORBIDDEN,
+                Some("Only superusers can assign the superuser role.".to_string()),
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,346 @@
+use crate::config::config;
+use crate::{err_json_gen, get_db_err, get_jwt_exp_timestamp, new_uuid_v1, now_local_time};
+use crate::{Claims, Keys, ADMIN_KEY, USER_KEY};
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use jsonwebtoken::{decode, encode, Header, Validation};
+use rand::Rng;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::types::Uuid;
+use sqlx::{Pool, Postgres};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Token持有者類型，決定簽章金鑰與資料庫內的account_type
+#[derive(Clone, Copy)]
+pub enum TokenOwner {
+    Admin,
+    User,
+}
+
+impl TokenOwner {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            TokenOwner::Admin => "admin",
+            TokenOwner::User => "user",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            TokenOwner::Admin => "an administrator",
+            TokenOwner::User => "an user",
+        }
+    }
+
+    fn keys(&self) -> &'static Keys {
+        match self {
+            TokenOwner::Admin => &ADMIN_KEY,
+            TokenOwner::User => &USER_KEY,
+        }
+    }
+}
+
+/// 登入與換發Token時回傳的內容
+#[derive(Serialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_in: u64,
+}
+
+/// Refresh Token、一次性Token與API Key只保存SHA-256雜湊值，資料庫外洩也無法直接使用
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// 產生不透明的Token（256位元亂數，十六進位字串）
+pub fn new_opaque_token() -> String {
+    rand::thread_rng()
+        .gen::<[u8; 32]>()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// 簽發Access Token與Refresh Token（family為None時代表新的登入，會建立新的Token家族）
+pub async fn issue_token_pair(
+    db: &Pool<Postgres>,
+    owner: TokenOwner,
+    uuid: Uuid,
+    family: Option<Uuid>,
+) -> Result<TokenPair, (StatusCode, Json<Value>)> {
+    let claims = Claims {
+        uuid,
+        jti: new_uuid_v1(),
+        exp: get_jwt_exp_timestamp(),
+    };
+    let access_token =
+        encode(&Header::default(), &claims, &owner.keys().encoding).map_err(|error| {
+            err_json_gen(StatusCode::INTERNAL_SERVER_ERROR, Some(error.to_string()))
+        })?;
+    let refresh_token = new_opaque_token();
+    sqlx::query!(
+        r#"
+        insert into backendmodulesdb.refresh_token (
+            token_hash,
+            family_uuid,
+            account_uuid,
+            account_type,
+            access_jti,
+            expire_timestamp
+        )
+        values ($1, $2, $3, $4, $5, $6);
+        "#,
+        hash_token(&refresh_token),
+        family.unwrap_or_else(new_uuid_v1),
+        uuid,
+        owner.as_str(),
+        claims.jti,
+        now_local_time() + time::Duration::seconds(config().jwt.refresh_token_ttl as i64)
+    )
+    .execute(db)
+    .await
+    .map_err(get_db_err)?;
+    Ok(TokenPair {
+        access_token,
+        refresh_token,
+        expires_in: claims.exp
+            - SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+    })
+}
+
+/// 以Refresh Token換發新的一組Token，舊的Refresh Token隨即失效；
+/// 已使用過的Refresh Token再次出現時視為外洩，撤銷整個Token家族
+pub async fn rotate_refresh_token(
+    db: &Pool<Postgres>,
+    owner: TokenOwner,
+    refresh_token: &str,
+) -> Result<TokenPair, (StatusCode, Json<Value>)> {
+    let token_hash = hash_token(refresh_token);
+    let rotated = sqlx::query!(
+        r#"
+        update backendmodulesdb.refresh_token
+        set used = true
+        where token_hash = $1
+            and account_type = $2
+            and used = false
+            and revoked = false
+            and expire_timestamp > now()
+        returning family_uuid, account_uuid;
+        "#,
+        token_hash,
+        owner.as_str()
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(get_db_err)?;
+
+    if let Some(record) = rotated {
+        return issue_token_pair(db, owner, record.account_uuid, Some(record.family_uuid)).await;
+    }
+
+    let reused = sqlx::query!(
+        r#"
+        select family_uuid from backendmodulesdb.refresh_token
+        where token_hash = $1 and account_type = $2 and (used = true or revoked = true);
+        "#,
+        token_hash,
+        owner.as_str()
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(get_db_err)?;
+
+    match reused {
+        Some(record) => {
+            tracing::warn!(
+                "Refresh token reuse detected, revoking token family {}.",
+                record.family_uuid
+            );
+            revoke_family(db, record.family_uuid).await?;
+            Err(err_json_gen(
+                StatusCode::UNAUTHORIZED,
+                Some(
+                    "Refresh token has already been used. All sessions issued from this login have been revoked, please log in again."
+                        .to_string(),
+                ),
+            ))
+        }
+        None => Err(err_json_gen(
+            StatusCode::UNAUTHORIZED,
+            Some("Refresh token is invalid or expired, please log in again.".to_string()),
+        )),
+    }
+}
+
+/// 撤銷整個Token家族（所有Refresh Token與其簽發過的Access Token）
+pub async fn revoke_family(
+    db: &Pool<Postgres>,
+    family_uuid: Uuid,
+) -> Result<(), (StatusCode, Json<Value>)> {
+    let mut transaction = db.begin().await.map_err(get_db_err)?;
+    sqlx::query!(
+        r#"
+        insert into backendmodulesdb.revoked_token (jti, expire_timestamp)
+        select access_jti, expire_timestamp from backendmodulesdb.refresh_token
+        where family_uuid = $1
+        on conflict (jti) do nothing;
+        "#,
+        family_uuid
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(get_db_err)?;
+    sqlx::query!(
+        "update backendmodulesdb.refresh_token set revoked = true where family_uuid = $1",
+        family_uuid
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(get_db_err)?;
+    transaction.commit().await.map_err(get_db_err)
+}
+
+/// 撤銷帳號的所有登入（所有Token家族），用於重設或變更密碼後
+pub async fn revoke_account_sessions(
+    db: &Pool<Postgres>,
+    owner: TokenOwner,
+    account_uuid: Uuid,
+) -> Result<(), (StatusCode, Json<Value>)> {
+    let mut transaction = db.begin().await.map_err(get_db_err)?;
+    sqlx::query!(
+        r#"
+        insert into backendmodulesdb.revoked_token (jti, expire_timestamp)
+        select access_jti, expire_timestamp from backendmodulesdb.refresh_token
+        where account_uuid = $1 and account_type = $2 and revoked = false
+        on conflict (jti) do nothing;
+        "#,
+        account_uuid,
+        owner.as_str()
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(get_db_err)?;
+    sqlx::query!(
+        r#"
+        update backendmodulesdb.refresh_token set revoked = true
+        where account_uuid = $1 and account_type = $2 and revoked = false;
+        "#,
+        account_uuid,
+        owner.as_str()
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(get_db_err)?;
+    transaction.commit().await.map_err(get_db_err)
+}
+
+/// 登出：撤銷目前的Access Token與同一家族的Refresh Token
+pub async fn revoke_session(
+    db: &Pool<Postgres>,
+    claims: &Claims,
+) -> Result<(), (StatusCode, Json<Value>)> {
+    // 順便清除已過期（不需再比對）的撤銷紀錄
+    sqlx::query!("delete from backendmodulesdb.revoked_token where expire_timestamp < now()")
+        .execute(db)
+        .await
+        .map_err(get_db_err)?;
+    let family = sqlx::query!(
+        "select family_uuid from backendmodulesdb.refresh_token where access_jti = $1",
+        claims.jti
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(get_db_err)?;
+    match family {
+        Some(record) => revoke_family(db, record.family_uuid).await,
+        None => sqlx::query!(
+            r#"
+            insert into backendmodulesdb.revoked_token (jti, expire_timestamp)
+            values ($1, to_timestamp($2))
+            on conflict (jti) do nothing;
+            "#,
+            claims.jti,
+            claims.exp as f64
+        )
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(get_db_err),
+    }
+}
+
+/// 取得驗證Token時使用的資料庫連線（撤銷清單），需要Extension<Pool<Postgres>>
+pub(crate) async fn token_db<S>(
+    parts: &mut Parts,
+    state: &S,
+) -> Result<Pool<Postgres>, (StatusCode, Json<Value>)>
+where
+    S: Send + Sync,
+{
+    Extension::<Pool<Postgres>>::from_request_parts(parts, state)
+        .await
+        .map(|Extension(db)| db)
+        .map_err(|_| {
+            err_json_gen(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Some("Token revocation list is unavailable on this server.".to_string()),
+            )
+        })
+}
+
+/// 驗證Access Token（簽章、過期時間與撤銷清單），成功時回傳Token內容
+pub async fn verify_token(
+    owner: TokenOwner,
+    token: &str,
+    db: &Pool<Postgres>,
+) -> Result<Claims, (StatusCode, Json<Value>)> {
+    let token_data = decode::<Claims>(token, &owner.keys().decoding, &Validation::default())
+        .map_err(|_| {
+            err_json_gen(
+                StatusCode::UNAUTHORIZED,
+                Some(format!(
+                    "Unable to parse token. This API needs a token for {}.",
+                    owner.description()
+                )),
+            )
+        })?;
+
+    if token_data.claims.exp
+        < SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    {
+        return Err(err_json_gen(
+            StatusCode::UNAUTHORIZED,
+            Some("Token expired, please log in again.".to_string()),
+        ));
+    }
+
+    let record = sqlx::query!(
+        "select count(*) from backendmodulesdb.revoked_token where jti = $1",
+        token_data.claims.jti
+    )
+    .fetch_one(db)
+    .await
+    .map_err(get_db_err)?;
+    if record.count.unwrap_or(0) == 0 {
+        Ok(token_data.claims)
+    } else {
+        Err(err_json_gen(
+            StatusCode::UNAUTHORIZED,
+            Some("Token revoked, please log in again.".to_string()),
+        ))
+    }
+}
@@ -0,0 +1,190 @@
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use futures::StreamExt;
+use redis::aio::{ConnectionManager, PubSub};
+use redis::{Client, RedisResult};
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// 每個聊天室本機broadcast channel的容量
+const ROOM_CHANNEL_CAPACITY: usize = 100;
+
+/// 聊天室事件的Redis pub/sub頻道前綴（頻道名稱為前綴加上聊天室UUID）
+const ROOM_CHANNEL_PREFIX: &str = "chatroom_events:";
+
+/// Redis pub/sub連線中斷後重新連線的間隔
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 本機的聊天室：broadcast channel與各用戶的連線數
+struct Room {
+    tx: broadcast::Sender<String>,
+    members: HashMap<Uuid, usize>,
+}
+
+/// 聊天室登記與事件分送
+///
+/// 聊天室存放在分片的DashMap，不需要全域鎖；最後一個連線離開時移除聊天室與其broadcast channel。
+/// 單機模式直接送到本機的broadcast channel；Redis模式先發布到Redis，
+/// 所有節點（包含自己）收到後再轉送給本機加入該聊天室的連線，因此可以同時執行多個節點
+pub struct Fanout {
+    rooms: DashMap<String, Room>,
+    publisher: Option<ConnectionManager>,
+}
+
+/// 連線加入聊天室的紀錄，釋放時離開聊天室
+pub struct Membership {
+    fanout: Arc<Fanout>,
+    chatroom_uuid: String,
+    user_uuid: Uuid,
+}
+
+impl Drop for Membership {
+    fn drop(&mut self) {
+        self.fanout.leave(&self.chatroom_uuid, self.user_uuid);
+    }
+}
+
+impl Fanout {
+    /// 單機模式
+    pub fn local() -> Arc<Fanout> {
+        Arc::new(Fanout {
+            rooms: DashMap::new(),
+            publisher: None,
+        })
+    }
+
+    /// Redis模式，回傳前已訂閱完成，之後發布的事件都會收到
+    pub async fn redis(client: Client) -> RedisResult<Arc<Fanout>> {
+        let publisher = ConnectionManager::new(client.clone()).await?;
+        let pubsub = subscribe(&client).await?;
+        let fanout = Arc::new(Fanout {
+            rooms: DashMap::new(),
+            publisher: Some(publisher),
+        });
+        tokio::spawn(listen(client, pubsub, Arc::downgrade(&fanout)));
+        Ok(fanout)
+    }
+
+    /// 加入聊天室並接收事件，回傳的Membership釋放時離開聊天室
+    pub fn join(
+        self: &Arc<Self>,
+        chatroom_uuid: &str,
+        user_uuid: Uuid,
+    ) -> (Membership, broadcast::Receiver<String>) {
+        let mut room = self
+            .rooms
+            .entry(chatroom_uuid.to_string())
+            .or_insert_with(|| Room {
+                tx: broadcast::channel(ROOM_CHANNEL_CAPACITY).0,
+                members: HashMap::new(),
+            });
+        *room.members.entry(user_uuid).or_insert(0) += 1;
+        let rx = room.tx.subscribe();
+        let membership = Membership {
+            fanout: self.clone(),
+            chatroom_uuid: chatroom_uuid.to_string(),
+            user_uuid,
+        };
+        (membership, rx)
+    }
+
+    /// 離開聊天室，最後一個連線離開時移除聊天室
+    fn leave(&self, chatroom_uuid: &str, user_uuid: Uuid) {
+        if let Entry::Occupied(mut room) = self.rooms.entry(chatroom_uuid.to_string()) {
+            let members = &mut room.get_mut().members;
+            if let Some(count) = members.get_mut(&user_uuid) {
+                *count -= 1;
+                if *count == 0 {
+                    members.remove(&user_uuid);
+                }
+            }
+            if members.is_empty() {
+                room.remove();
+            }
+        }
+    }
+
+    /// 本機目前的聊天室數量
+    pub fn room_count(&self) -> usize {
+        self.rooms.len()
+    }
+
+    /// 本機聊天室內的用戶數量（同一用戶的多個連線只計算一次）
+    pub fn member_count(&self, chatroom_uuid: &str) -> usize {
+        self.rooms
+            .get(chatroom_uuid)
+            .map(|room| room.members.len())
+            .unwrap_or(0)
+    }
+
+    /// 發送事件給聊天室內的所有連線
+    pub async fn publish(&self, chatroom_uuid: &str, event: String) {
+        match &self.publisher {
+            None => self.deliver(chatroom_uuid, event),
+            Some(publisher) => {
+                if let Err(error) = redis::cmd("publish")
+                    .arg(format!("{}{}", ROOM_CHANNEL_PREFIX, chatroom_uuid))
+                    .arg(event)
+                    .query_async::<_, ()>(&mut publisher.clone())
+                    .await
+                {
+                    tracing::warn!("Unable to publish chat event: {}", error);
+                }
+            }
+        }
+    }
+
+    /// 送給本機加入該聊天室的連線（本機沒有該聊天室時略過）
+    fn deliver(&self, chatroom_uuid: &str, event: String) {
+        if let Some(room) = self.rooms.get(chatroom_uuid) {
+            let _ = room.tx.send(event);
+        }
+    }
+}
+
+async fn subscribe(client: &Client) -> RedisResult<PubSub> {
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    pubsub
+        .psubscribe(format!("{}*", ROOM_CHANNEL_PREFIX))
+        .await?;
+    Ok(pubsub)
+}
+
+/// 接收所有節點發布的事件並轉送到本機，連線中斷時自動重新訂閱，Fanout釋放後結束
+async fn listen(client: Client, mut pubsub: PubSub, fanout: Weak<Fanout>) {
+    loop {
+        {
+            let mut messages = pubsub.on_message();
+            while let Some(message) = messages.next().await {
+                let fanout = match fanout.upgrade() {
+                    Some(fanout) => fanout,
+                    None => return,
+                };
+                let chatroom_uuid = message
+                    .get_channel_name()
+                    .trim_start_matches(ROOM_CHANNEL_PREFIX);
+                match message.get_payload::<String>() {
+                    Ok(event) => fanout.deliver(chatroom_uuid, event),
+                    Err(error) => tracing::warn!("Unable to read chat event: {}", error),
+                }
+            }
+        }
+
+        tracing::warn!("Redis pub/sub connection lost, resubscribing.");
+        pubsub = loop {
+            if fanout.strong_count() == 0 {
+                return;
+            }
+            match subscribe(&client).await {
+                Ok(pubsub) => break pubsub,
+                Err(error) => {
+                    tracing::warn!("Unable to resubscribe to chat events: {}", error);
+                    tokio::time::sleep(RECONNECT_INTERVAL).await;
+                }
+            }
+        };
+    }
+}
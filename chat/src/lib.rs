@@ -0,0 +1,16 @@
+#![forbid(unsafe_code)]
+pub mod fanout;
+pub mod protocol;
+pub mod service;
+
+use fanout::Fanout;
+use redis::aio::ConnectionManager;
+use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+
+/// 各欄位皆可在連線間共用，不需要額外加鎖
+pub struct AppState {
+    pub fanout: Arc<Fanout>,
+    pub redis: ConnectionManager,
+    pub db: Pool<Postgres>,
+}
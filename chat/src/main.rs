@@ -1,64 +1,57 @@
 #![forbid(unsafe_code)]
-mod service;
-
 use axum::routing::get;
 use axum::Router;
+use base_library::config::{ChatFanout, Config};
 use base_library::default_fallback;
-use dotenvy::dotenv;
+use chat::fanout::Fanout;
+use chat::service::websocket_handler;
+use chat::AppState;
 use mimalloc::MiMalloc;
-use r2d2::Pool;
+use redis::aio::ConnectionManager;
 use redis::Client;
-use std::collections::{HashMap, HashSet};
-use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
-
-use service::websocket_handler;
-use tokio::sync::broadcast;
-use tokio::sync::broadcast::Sender;
+use sqlx::postgres::PgPoolOptions;
+use std::sync::Arc;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
-pub struct AppState {
-    pub chatroom_set: Mutex<HashMap<String, HashSet<String>>>,
-    pub tx: Mutex<HashMap<String, Sender<String>>>,
-    pub pool: Pool<Client>,
-}
-
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    dotenv().ok();
+    let config = Config::init(3501).expect("Configuration failed.");
 
-    let chatroom_set = Mutex::new(HashMap::new());
-    let (tx, _rx) = broadcast::channel(100);
+    let client = Client::open(config.redis.url.as_str())
+        .expect("Could not connect to Redis, is Redis ready?");
 
-    let hash_map_tx = Mutex::new(HashMap::new());
-    hash_map_tx
-        .try_lock()
-        .unwrap()
-        .insert("root".to_string(), tx);
-
-    let pool = Pool::builder()
-        .max_size(100)
-        .build(
-            Client::open("redis://127.0.0.1/")
-                .expect("Could not connect to Redis, is Redis ready?"),
-        )
-        .unwrap();
+    // 單一多工連線，斷線時自動重新連線
+    let redis = ConnectionManager::new(client.clone())
+        .await
+        .expect("Could not connect to Redis, is Redis ready?");
+
+    // 多節點部署時透過Redis pub/sub轉送聊天室事件
+    let fanout = match config.chat.fanout {
+        ChatFanout::Local => Fanout::local(),
+        ChatFanout::Redis => Fanout::redis(client)
+            .await
+            .expect("Could not subscribe to Redis, is Redis ready?"),
+    };
+
+    // 驗證Token時需要查詢撤銷清單
+    let db = PgPoolOptions::new()
+        .max_connections(config.database.max_connections)
+        .min_connections(config.database.min_connections)
+        .connect(&config.database.url())
+        .await
+        .expect("Database connection failed.");
 
-    let app_state = Arc::new(Mutex::new(AppState {
-        chatroom_set,
-        tx: hash_map_tx,
-        pool,
-    }));
+    let app_state = Arc::new(AppState { fanout, redis, db });
 
     let app = Router::with_state(app_state)
         .route("/", get(websocket_handler))
         .fallback(default_fallback);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3501));
+    let addr = config.bind_address();
 
     println!("Listening on {}", addr);
 
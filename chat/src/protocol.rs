@@ -0,0 +1,118 @@
+use base_library::{new_uuid_v1, now_local_time};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// 聊天室協定版本，客戶端每個訊框都需帶上`"v": 1`
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// 客戶端送出的訊框
+#[derive(Deserialize)]
+pub struct ClientFrame {
+    pub v: u8,
+    /// 客戶端自訂的去重ID，重送同一則訊息時帶上相同的值，伺服器不會重複廣播
+    pub client_id: Option<String>,
+    #[serde(flatten)]
+    pub event: ClientEvent,
+}
+
+/// 客戶端可送出的事件
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientEvent {
+    /// 加入聊天室（連線後的第一個訊框），chatroom_uuid為null時建立新聊天室
+    Join { chatroom_uuid: Option<Uuid> },
+    /// 聊天訊息
+    Message { text: String },
+    /// 正在輸入
+    Typing,
+    /// 查詢歷史訊息，before為上一頁的next_before（不包含），未提供時從最新一筆開始
+    History {
+        before: Option<String>,
+        count: Option<usize>,
+    },
+}
+
+/// 伺服器送出的事件
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServerEvent {
+    pub v: u8,
+    /// 事件ID（ack事件為被確認的訊息ID）
+    pub id: Uuid,
+    pub sender_uuid: Uuid,
+    /// 尚未加入聊天室時為null
+    pub chatroom_uuid: Option<Uuid>,
+    #[serde(with = "time::serde::iso8601")]
+    pub timestamp: OffsetDateTime,
+    pub client_id: Option<String>,
+    #[serde(flatten)]
+    pub body: EventBody,
+}
+
+/// 伺服器事件的內容
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventBody {
+    Message {
+        text: String,
+    },
+    Join,
+    Leave,
+    Typing,
+    /// 訊息已保存並廣播
+    Ack,
+    /// 客戶端訊框無法處理，連線不會因此中斷
+    Error {
+        reason: String,
+    },
+    /// 歷史訊息（由舊到新排列），next_before為下一頁的游標（沒有更多訊息時為null）
+    History {
+        messages: Vec<ServerEvent>,
+        next_before: Option<String>,
+    },
+}
+
+impl ServerEvent {
+    pub fn new(
+        sender_uuid: Uuid,
+        chatroom_uuid: Option<Uuid>,
+        client_id: Option<String>,
+        body: EventBody,
+    ) -> Self {
+        ServerEvent {
+            v: PROTOCOL_VERSION,
+            id: new_uuid_v1(),
+            sender_uuid,
+            chatroom_uuid,
+            timestamp: now_local_time(),
+            client_id,
+            body,
+        }
+    }
+
+    pub fn error(sender_uuid: Uuid, chatroom_uuid: Option<Uuid>, reason: String) -> Self {
+        ServerEvent::new(
+            sender_uuid,
+            chatroom_uuid,
+            None,
+            EventBody::Error { reason },
+        )
+    }
+
+    pub fn to_text(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// 解析客戶端訊框，失敗時回傳可放入error事件的原因
+pub fn parse_client_frame(text: &str) -> Result<ClientFrame, String> {
+    let frame = serde_json::from_str::<ClientFrame>(text)
+        .map_err(|error| format!("Malformed frame: {}.", error))?;
+    if frame.v != PROTOCOL_VERSION {
+        return Err(format!(
+            "Unsupported protocol version {}, this server speaks version {}.",
+            frame.v, PROTOCOL_VERSION
+        ));
+    }
+    Ok(frame)
+}
@@ -1,124 +1,333 @@
+use crate::fanout::Fanout;
+use crate::protocol::{parse_client_frame, ClientEvent, EventBody, ServerEvent};
 use crate::AppState;
-use axum::extract::ws::{Message, WebSocket};
-use axum::extract::{State, WebSocketUpgrade};
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
+use axum::extract::{Query, State, WebSocketUpgrade};
+use axum::headers::authorization::Bearer;
+use axum::headers::Authorization;
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use base_library::new_uuid_v1;
+use axum::{Json, TypedHeader};
+use base_library::token::{verify_token, TokenOwner};
+use base_library::{err_json_gen, new_uuid_v1, Claims};
 use futures::{SinkExt, StreamExt};
+use redis::aio::ConnectionManager;
+use redis::streams::StreamRangeReply;
+use redis::{RedisResult, Script};
 use serde::Deserialize;
-use std::collections::HashSet;
-use std::ops::Deref;
-use std::sync::{Arc, Mutex};
-use tokio::sync::broadcast;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
 // Modified from https://github.com/tokio-rs/axum/blob/main/examples/chat/src/main.rs
 // Add: Chat Room Support, Redis Cache Support (TBD).
 
+/// 加入聊天室時回傳的歷史訊息筆數
+const HISTORY_ON_JOIN: usize = 50;
+
+/// 單次查詢歷史訊息的筆數上限
+const HISTORY_PAGE_LIMIT: usize = 100;
+
+/// 每個聊天室的訊息stream保留的大約筆數（XADD MAXLEN ~）
+const MESSAGE_STREAM_MAX_LEN: usize = 10000;
+
+/// client_id去重紀錄的保留秒數
+const CLIENT_ID_TTL: usize = 3600;
+
+/// 無法在Authorization標頭帶Token的客戶端（如瀏覽器），可改用`?token=`傳入
 #[derive(Deserialize)]
-struct FirstMsg {
+pub struct TokenQuery {
+    token: Option<String>,
+}
+
+/// 聊天室訊息的Redis stream key
+fn message_stream_key(chatroom_uuid: &Uuid) -> String {
+    format!("chatroom_messages:{}", chatroom_uuid)
+}
+
+/// 讀取歷史訊息（由舊到新排列），next_before為下一頁的游標（沒有更多訊息時為null）
+async fn load_history(
+    connection: &mut ConnectionManager,
     user_uuid: Uuid,
-    chatroom_uuid: Option<Uuid>
+    chatroom_uuid: Uuid,
+    before: Option<&str>,
+    count: usize,
+) -> RedisResult<ServerEvent> {
+    let count = count.clamp(1, HISTORY_PAGE_LIMIT);
+    let end = before
+        .map(|before| format!("({}", before))
+        .unwrap_or_else(|| "+".to_string());
+    let reply = redis::cmd("xrevrange")
+        .arg(message_stream_key(&chatroom_uuid))
+        .arg(end)
+        .arg("-")
+        .arg("COUNT")
+        .arg(count)
+        .query_async::<_, StreamRangeReply>(connection)
+        .await?;
+    let next_before = if reply.ids.len() == count {
+        reply.ids.last().map(|stream_id| stream_id.id.clone())
+    } else {
+        None
+    };
+    let messages = reply
+        .ids
+        .into_iter()
+        .rev()
+        .filter_map(|stream_id| stream_id.get::<String>("event"))
+        .filter_map(|event| serde_json::from_str::<ServerEvent>(&event).ok())
+        .collect();
+    Ok(ServerEvent::new(
+        user_uuid,
+        Some(chatroom_uuid),
+        None,
+        EventBody::History {
+            messages,
+            next_before,
+        },
+    ))
+}
+
+/// client_id去重紀錄的key，沒有client_id時為None
+fn client_id_key(event: &ServerEvent) -> Option<String> {
+    match (event.chatroom_uuid, &event.client_id) {
+        (Some(chatroom_uuid), Some(client_id)) => Some(format!(
+            "chatroom_client_ids:{}:{}:{}",
+            chatroom_uuid, event.sender_uuid, client_id
+        )),
+        _ => None,
+    }
+}
+
+/// 保存訊息並記下client_id的Lua腳本（KEYS[1]為訊息stream，KEYS[2]為選填的client_id key）
+///
+/// 已保存過同一個client_id時不寫入並回傳第一次的訊息ID；先寫入stream再記下client_id，寫入失敗時客戶端可以重送
+const PERSIST_MESSAGE_SCRIPT: &str = r#"
+if KEYS[2] then
+    local existing = redis.call('GET', KEYS[2])
+    if existing then
+        return existing
+    end
+end
+redis.call('XADD', KEYS[1], 'MAXLEN', '~', ARGV[1], '*', 'event', ARGV[2])
+if KEYS[2] then
+    redis.call('SET', KEYS[2], ARGV[3], 'EX', ARGV[4])
+end
+return false
+"#;
+
+/// 保存訊息並以client_id去重（Redis腳本內完成，同時送出的重複訊息只會保存一次），重複時回傳第一次的訊息ID
+async fn persist_message(
+    connection: &mut ConnectionManager,
+    chatroom_uuid: &Uuid,
+    event: &ServerEvent,
+) -> RedisResult<Option<Uuid>> {
+    let script = Script::new(PERSIST_MESSAGE_SCRIPT);
+    let mut invocation = script.key(message_stream_key(chatroom_uuid));
+    if let Some(key) = client_id_key(event) {
+        invocation.key(key);
+    }
+    Ok(invocation
+        .arg(MESSAGE_STREAM_MAX_LEN)
+        .arg(event.to_text())
+        .arg(event.id.to_string())
+        .arg(CLIENT_ID_TTL)
+        .invoke_async::<_, Option<String>>(connection)
+        .await?
+        .and_then(|id| id.parse().ok()))
+}
+
+/// 廣播事件給聊天室內的所有連線
+async fn broadcast_event(fanout: &Fanout, event: &ServerEvent) {
+    if let Some(chatroom_uuid) = event.chatroom_uuid {
+        fanout
+            .publish(&chatroom_uuid.to_string(), event.to_text())
+            .await;
+    }
+}
+
+/// 產生關閉連線的訊框，驗證失敗為1008（Policy Violation），伺服器錯誤為1011
+fn close_message((status_code, Json(error)): (StatusCode, Json<Value>)) -> Message {
+    let code = if status_code == StatusCode::UNAUTHORIZED {
+        close_code::POLICY
+    } else {
+        close_code::ERROR
+    };
+    let reason = error["reason"]
+        .as_str()
+        .or_else(|| error["message"].as_str())
+        .unwrap_or_default()
+        .to_string();
+    Message::Close(Some(CloseFrame {
+        code,
+        reason: reason.into(),
+    }))
 }
 
 pub async fn websocket_handler(
     ws: WebSocketUpgrade,
-    State(state): State<Arc<Mutex<AppState>>>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    Query(query): Query<TokenQuery>,
+    State(state): State<Arc<AppState>>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(|socket| websocket(socket, state))
+    let token = bearer
+        .map(|TypedHeader(Authorization(bearer))| bearer.token().to_string())
+        .or(query.token);
+    let claims = match token {
+        Some(token) => verify_token(TokenOwner::User, &token, &state.db).await,
+        None => Err(err_json_gen(
+            StatusCode::UNAUTHORIZED,
+            Some("Unable to extract token from request. Please log in.".to_string()),
+        )),
+    };
+    // 升級後再以Close訊框拒絕，瀏覽器的WebSocket API無法讀取握手階段的HTTP錯誤
+    ws.on_upgrade(|mut socket| async move {
+        match claims {
+            Ok(claims) => websocket(socket, state, claims).await,
+            Err(rejection) => {
+                let _ = socket.send(close_message(rejection)).await;
+            }
+        }
+    })
 }
 
-async fn websocket(stream: WebSocket, state: Arc<Mutex<AppState>>) {
+async fn websocket(stream: WebSocket, state: Arc<AppState>, claims: Claims) {
     // By splitting we can send and receive at the same time.
     let (mut sender, mut receiver) = stream.split();
 
-    let mut chatroom_uuid = String::new();
+    let mut joined = None;
 
-    let mut user_uuid = String::new();
+    let user_uuid = claims.uuid;
 
-    let mut connection = state.try_lock().unwrap().pool.get().unwrap();
+    let mut connection = state.redis.clone();
 
-    // Loop until a text message is found.
-    while let Some(Ok(message)) = receiver.next().await {
-        if let Message::Text(first_msg) = message {
-            match serde_json::from_str::<FirstMsg>(&first_msg) {
-                Ok(first_msg) => {
-                    chatroom_uuid = if first_msg.chatroom_uuid.is_none() {
-                        new_uuid_v1().to_string()
-                    } else {
-                        first_msg.chatroom_uuid.unwrap().to_string()
-                    };
+    let fanout = state.fanout.clone();
 
-                    user_uuid = first_msg.user_uuid.to_string();
+    // Loop until a join event is found.
+    while let Some(Ok(message)) = receiver.next().await {
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => return,
+            _ => continue,
+        };
+        let reply = match parse_client_frame(&text) {
+            Ok(frame) => match frame.event {
+                ClientEvent::Join { chatroom_uuid } => {
+                    let chatroom_uuid = chatroom_uuid.unwrap_or_else(new_uuid_v1);
 
-                    redis::cmd("xadd")
+                    if let Err(error) = redis::pipe()
+                        .cmd("xadd")
                         .arg("chatroom")
                         .arg("*")
                         .arg("id")
-                        .arg(&chatroom_uuid)
-                        .query::<String>(&mut connection)
-                        .unwrap();
-
-                    redis::cmd("xadd")
-                        .arg(format!("chatroom_users:{}", &chatroom_uuid))
+                        .arg(chatroom_uuid.to_string())
+                        .ignore()
+                        .cmd("xadd")
+                        .arg(format!("chatroom_users:{}", chatroom_uuid))
                         .arg("*")
                         .arg("users")
-                        .arg(&user_uuid)
-                        .query::<String>(&mut connection)
-                        .unwrap();
+                        .arg(user_uuid.to_string())
+                        .ignore()
+                        .query_async::<_, ()>(&mut connection)
+                        .await
+                    {
+                        tracing::warn!("Unable to register chatroom member: {}", error);
+                        let error = ServerEvent::error(
+                            user_uuid,
+                            None,
+                            "Unable to join the chatroom, please try again later.".to_string(),
+                        );
+                        if sender.send(Message::Text(error.to_text())).await.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
 
-                    prepare_connection(state.deref(), &chatroom_uuid, &user_uuid);
+                    // Subscribe before loading history, so no message falls in between.
+                    let (membership, rx) = fanout.join(&chatroom_uuid.to_string(), user_uuid);
 
-                    sender
-                        .send(Message::Text(format!(
-                            "Welcome {}! Your chatroom_uuid is {}.",
-                            &user_uuid, &chatroom_uuid
-                        )))
-                        .await
-                        .unwrap();
+                    // 補送離線期間的訊息
+                    match load_history(
+                        &mut connection,
+                        user_uuid,
+                        chatroom_uuid,
+                        None,
+                        HISTORY_ON_JOIN,
+                    )
+                    .await
+                    {
+                        Ok(history) => {
+                            if sender.send(Message::Text(history.to_text())).await.is_err() {
+                                return;
+                            }
+                        }
+                        Err(error) => tracing::warn!("Unable to load chat history: {}", error),
+                    }
 
+                    joined = Some((chatroom_uuid, frame.client_id, membership, rx));
                     break;
                 }
-                Err(rejection) => {
-                    sender
-                        .send(Message::Text(rejection.to_string()))
-                        .await
-                        .unwrap();
-                    return;
+                _ => {
+                    ServerEvent::error(user_uuid, None, "Please join a chatroom first.".to_string())
                 }
-            }
+            },
+            Err(reason) => ServerEvent::error(user_uuid, None, reason),
+        };
+        if sender.send(Message::Text(reply.to_text())).await.is_err() {
+            return;
         }
     }
 
-    let cloned_chatroom_uuid = chatroom_uuid.clone();
-    let cloned_user_uuid = user_uuid.clone();
-
-    // Subscribe before sending joined message.
-    let mut rx = state
-        .try_lock()
-        .unwrap()
-        .tx
-        .try_lock()
-        .unwrap()
-        .get(&cloned_chatroom_uuid)
-        .unwrap()
-        .subscribe();
+    // The client left before joining a chatroom.
+    // 離開時釋放_membership，最後一個連線離開的聊天室會被移除
+    let (chatroom_uuid, join_client_id, _membership, mut rx) = match joined {
+        Some(joined) => joined,
+        None => return,
+    };
 
     // Send joined message to all subscribers.
-    let msg = format!("{} joined.", user_uuid);
-    tracing::debug!("{}", msg);
-    let _ = state
-        .try_lock()
-        .unwrap()
-        .tx
-        .try_lock()
-        .unwrap()
-        .get(&*cloned_chatroom_uuid)
-        .unwrap()
-        .send(msg);
+    let join = ServerEvent::new(
+        user_uuid,
+        Some(chatroom_uuid),
+        join_client_id,
+        EventBody::Join,
+    );
+    tracing::debug!("{} joined {}.", user_uuid, chatroom_uuid);
+    broadcast_event(&fanout, &join).await;
+
+    // Replies that only go to this client (e.g. history pages, acks and errors).
+    let (direct_tx, mut direct_rx) = mpsc::unbounded_channel::<String>();
+
+    // Token到期時關閉連線，客戶端需以新的Token重新連線
+    let token_expiry = tokio::time::sleep(Duration::from_secs(
+        claims.exp.saturating_sub(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        ),
+    ));
 
     // This task will receive broadcast messages and send text message to our client.
     let mut send_task = tokio::spawn(async move {
-        while let Ok(msg) = rx.recv().await {
+        tokio::pin!(token_expiry);
+        loop {
+            let msg = tokio::select! {
+                Ok(msg) = rx.recv() => msg,
+                Some(msg) = direct_rx.recv() => msg,
+                _ = &mut token_expiry => {
+                    let _ = sender
+                        .send(close_message(err_json_gen(
+                            StatusCode::UNAUTHORIZED,
+                            Some("Token expired, please log in again.".to_string()),
+                        )))
+                        .await;
+                    break;
+                }
+                else => break,
+            };
             // In any websocket error, break loop.
             if sender.send(Message::Text(msg)).await.is_err() {
                 break;
@@ -126,19 +335,98 @@ async fn websocket(stream: WebSocket, state: Arc<Mutex<AppState>>) {
         }
     });
 
+    let recv_fanout = fanout.clone();
+
     // This task will receive messages from client and send them to broadcast subscribers.
     let mut recv_task = tokio::spawn(async move {
-        while let Some(Ok(Message::Text(text))) = receiver.next().await {
-            // Add username before message.
-            let _ = state
-                .try_lock()
-                .unwrap()
-                .tx
-                .try_lock()
-                .unwrap()
-                .get(&*cloned_chatroom_uuid)
-                .unwrap()
-                .send(format!("{}: {}", cloned_user_uuid, text));
+        while let Some(Ok(message)) = receiver.next().await {
+            let text = match message {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            let frame = match parse_client_frame(&text) {
+                Ok(frame) => frame,
+                Err(reason) => {
+                    let error = ServerEvent::error(user_uuid, Some(chatroom_uuid), reason);
+                    let _ = direct_tx.send(error.to_text());
+                    continue;
+                }
+            };
+            match frame.event {
+                ClientEvent::Join { .. } => {
+                    let error = ServerEvent::error(
+                        user_uuid,
+                        Some(chatroom_uuid),
+                        "Already joined a chatroom, please open another connection to join a different one."
+                            .to_string(),
+                    );
+                    let _ = direct_tx.send(error.to_text());
+                }
+                ClientEvent::History { before, count } => {
+                    let reply = load_history(
+                        &mut connection,
+                        user_uuid,
+                        chatroom_uuid,
+                        before.as_deref(),
+                        count.unwrap_or(HISTORY_ON_JOIN),
+                    )
+                    .await
+                    .unwrap_or_else(|error| {
+                        ServerEvent::error(user_uuid, Some(chatroom_uuid), error.to_string())
+                    });
+                    let _ = direct_tx.send(reply.to_text());
+                }
+                ClientEvent::Typing => {
+                    let typing = ServerEvent::new(
+                        user_uuid,
+                        Some(chatroom_uuid),
+                        frame.client_id,
+                        EventBody::Typing,
+                    );
+                    broadcast_event(&recv_fanout, &typing).await;
+                }
+                ClientEvent::Message { text } => {
+                    let message = ServerEvent::new(
+                        user_uuid,
+                        Some(chatroom_uuid),
+                        frame.client_id,
+                        EventBody::Message { text },
+                    );
+
+                    // Persist the message so clients that were offline can fetch it later.
+                    // 重送的訊息只回覆ack（第一次的訊息ID），不再保存與廣播；
+                    // 保存失敗時不廣播也不回覆ack，客戶端可以用同一個client_id重送
+                    let duplicate = match persist_message(&mut connection, &chatroom_uuid, &message)
+                        .await
+                    {
+                        Ok(duplicate) => duplicate,
+                        Err(error) => {
+                            tracing::warn!("Unable to persist chat message: {}", error);
+                            let mut error = ServerEvent::error(
+                                user_uuid,
+                                Some(chatroom_uuid),
+                                "Unable to save the message, please try again later.".to_string(),
+                            );
+                            error.client_id = message.client_id;
+                            let _ = direct_tx.send(error.to_text());
+                            continue;
+                        }
+                    };
+                    if duplicate.is_none() {
+                        broadcast_event(&recv_fanout, &message).await;
+                    }
+
+                    let mut ack = ServerEvent::new(
+                        user_uuid,
+                        Some(chatroom_uuid),
+                        message.client_id,
+                        EventBody::Ack,
+                    );
+                    ack.id = duplicate.unwrap_or(message.id);
+                    let _ = direct_tx.send(ack.to_text());
+                }
+            }
         }
     });
 
@@ -147,20 +435,8 @@ async fn websocket(stream: WebSocket, state: Arc<Mutex<AppState>>) {
         _ = (&mut send_task) => recv_task.abort(),
         _ = (&mut recv_task) => send_task.abort(),
     }
-}
 
-fn prepare_connection(state: &Mutex<AppState>, chatroom_uuid: &String, user_uuid: &str) {
-    let state_locked = &mut state.try_lock().unwrap();
-    let mut chatroom_set = state_locked.chatroom_set.try_lock().unwrap();
-    let mut new_user_set = match chatroom_set.get(chatroom_uuid) {
-        None => {
-            let (tx, _rx) = broadcast::channel(100);
-            let state_tx = &mut state_locked.tx.try_lock().unwrap();
-            state_tx.insert(chatroom_uuid.clone(), tx);
-            HashSet::new()
-        }
-        Some(_) => chatroom_set.remove(chatroom_uuid).unwrap(),
-    };
-    new_user_set.insert(user_uuid.parse().unwrap());
-    chatroom_set.insert(chatroom_uuid.clone(), new_user_set);
+    let leave = ServerEvent::new(user_uuid, Some(chatroom_uuid), None, EventBody::Leave);
+    tracing::debug!("{} left {}.", user_uuid, chatroom_uuid);
+    broadcast_event(&fanout, &leave).await;
 }
@@ -0,0 +1,218 @@
+use axum::routing::get;
+use axum::Router;
+use base_library::config::Config;
+use base_library::new_uuid_v1;
+use base_library::token::{issue_token_pair, TokenOwner};
+use chat::fanout::Fanout;
+use chat::protocol::{EventBody, ServerEvent};
+use chat::service::websocket_handler;
+use chat::AppState;
+use futures::{SinkExt, StreamExt};
+use redis::aio::ConnectionManager;
+use serde_json::json;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Pool, Postgres};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::Receiver;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use uuid::Uuid;
+
+type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn receive(rx: &mut Receiver<String>) -> String {
+    timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("Timed out waiting for a chat event.")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn local_fanout_only_reaches_the_same_room() {
+    let fanout = Fanout::local();
+    let chatroom_uuid = new_uuid_v1().to_string();
+    let other_chatroom_uuid = new_uuid_v1().to_string();
+    let (_membership, mut rx) = fanout.join(&chatroom_uuid, new_uuid_v1());
+    let (_other_membership, mut other_rx) = fanout.join(&other_chatroom_uuid, new_uuid_v1());
+
+    fanout.publish(&chatroom_uuid, "hello".to_string()).await;
+
+    assert_eq!(receive(&mut rx).await, "hello");
+    assert!(other_rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn room_is_removed_when_the_last_member_leaves() {
+    let fanout = Fanout::local();
+    let chatroom_uuid = new_uuid_v1().to_string();
+    let user_uuid = new_uuid_v1();
+
+    let (first, _rx) = fanout.join(&chatroom_uuid, user_uuid);
+    let (second, _rx) = fanout.join(&chatroom_uuid, user_uuid);
+    let (other, _rx) = fanout.join(&chatroom_uuid, new_uuid_v1());
+    assert_eq!(fanout.member_count(&chatroom_uuid), 2);
+
+    // 同一用戶還有其他連線時仍算在聊天室內
+    drop(first);
+    assert_eq!(fanout.member_count(&chatroom_uuid), 2);
+    drop(second);
+    assert_eq!(fanout.member_count(&chatroom_uuid), 1);
+    drop(other);
+    assert_eq!(fanout.room_count(), 0);
+}
+
+/// 在隨機的埠號啟動一個chat服務（Redis fanout），回傳位址
+async fn spawn_node(redis_url: &str, db: Pool<Postgres>) -> SocketAddr {
+    let client = redis::Client::open(redis_url).unwrap();
+    let redis = ConnectionManager::new(client.clone()).await.unwrap();
+    let fanout = Fanout::redis(client).await.unwrap();
+    let app = Router::with_state(Arc::new(AppState { fanout, redis, db }))
+        .route("/", get(websocket_handler));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service()),
+    );
+    addr
+}
+
+/// 連線到chat服務並加入聊天室，收到歷史訊息（已完成訂閱）後回傳
+async fn join(addr: SocketAddr, token: &str, chatroom_uuid: Uuid) -> Client {
+    let (mut client, _) = connect_async(format!("ws://{}/?token={}", addr, token))
+        .await
+        .expect("Unable to connect to the chat server.");
+    client
+        .send(WsMessage::Text(
+            json!({ "v": 1, "type": "join", "chatroom_uuid": chatroom_uuid }).to_string(),
+        ))
+        .await
+        .unwrap();
+    next_event(&mut client, |body| {
+        matches!(body, EventBody::History { .. })
+    })
+    .await;
+    client
+}
+
+/// 讀取下一個符合條件的事件（略過其他事件，例如其他連線的join）
+async fn next_event(client: &mut Client, wanted: impl Fn(&EventBody) -> bool) -> ServerEvent {
+    loop {
+        let message = timeout(Duration::from_secs(5), client.next())
+            .await
+            .expect("Timed out waiting for a chat event.")
+            .expect("Connection closed.")
+            .unwrap();
+        if let WsMessage::Text(text) = message {
+            let event: ServerEvent = serde_json::from_str(&text).unwrap();
+            if let EventBody::Error { reason } = &event.body {
+                panic!("Chat server returned an error: {}", reason);
+            }
+            if wanted(&event.body) {
+                return event;
+            }
+        }
+    }
+}
+
+/// 送出聊天訊息，等待ack與廣播回自己的訊息（兩者的順序不固定）
+async fn send_message(client: &mut Client, text: &str) {
+    client
+        .send(WsMessage::Text(
+            json!({ "v": 1, "client_id": new_uuid_v1(), "type": "message", "text": text })
+                .to_string(),
+        ))
+        .await
+        .unwrap();
+    let (mut acked, mut echoed) = (false, false);
+    while !(acked && echoed) {
+        match next_event(client, |body| {
+            matches!(body, EventBody::Ack | EventBody::Message { .. })
+        })
+        .await
+        .body
+        {
+            EventBody::Ack => acked = true,
+            EventBody::Message { text: echo } => {
+                assert_eq!(echo, text);
+                echoed = true;
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// 收到的下一則聊天訊息內容
+async fn next_message(client: &mut Client) -> String {
+    match next_event(client, |body| matches!(body, EventBody::Message { .. }))
+        .await
+        .body
+    {
+        EventBody::Message { text } => text,
+        _ => unreachable!(),
+    }
+}
+
+/// 需要Redis（REDIS_URL）與PostgreSQL（DATABASE_URL，驗證Token時查詢撤銷清單），以`cargo test -- --ignored`執行
+#[tokio::test]
+#[ignore = "requires REDIS_URL and DATABASE_URL"]
+async fn two_servers_share_rooms_through_redis() {
+    let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL is not set.");
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set.");
+    Config::init_with(Config::default());
+    let db = PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&database_url)
+        .await
+        .unwrap();
+    let node_a = spawn_node(&redis_url, db.clone()).await;
+    let node_b = spawn_node(&redis_url, db.clone()).await;
+    let user_a = issue_token_pair(&db, TokenOwner::User, new_uuid_v1(), None)
+        .await
+        .unwrap()
+        .access_token;
+    let user_b = issue_token_pair(&db, TokenOwner::User, new_uuid_v1(), None)
+        .await
+        .unwrap()
+        .access_token;
+
+    let chatroom_uuid = new_uuid_v1();
+    let mut client_a = join(node_a, &user_a, chatroom_uuid).await;
+    let mut client_b = join(node_b, &user_b, chatroom_uuid).await;
+
+    send_message(&mut client_a, "from a").await;
+    assert_eq!(next_message(&mut client_b).await, "from a");
+
+    send_message(&mut client_b, "from b").await;
+    assert_eq!(next_message(&mut client_a).await, "from b");
+
+    // 其他聊天室的事件不會送到這個聊天室
+    let other_chatroom_uuid = new_uuid_v1();
+    let mut other_client = join(node_b, &user_b, other_chatroom_uuid).await;
+    let mut elsewhere_client = join(node_a, &user_a, other_chatroom_uuid).await;
+    send_message(&mut elsewhere_client, "elsewhere").await;
+    assert_eq!(next_message(&mut other_client).await, "elsewhere");
+    send_message(&mut client_a, "after").await;
+    assert_eq!(next_message(&mut client_b).await, "after");
+
+    // 同一用戶的兩個連線同時送出相同client_id的訊息，只會保存與廣播一次
+    let mut second_client_a = join(node_b, &user_a, chatroom_uuid).await;
+    let frame = WsMessage::Text(
+        json!({ "v": 1, "client_id": new_uuid_v1(), "type": "message", "text": "once" })
+            .to_string(),
+    );
+    let (first, second) = tokio::join!(client_a.send(frame.clone()), second_client_a.send(frame));
+    first.unwrap();
+    second.unwrap();
+    let first_ack = next_event(&mut client_a, |body| matches!(body, EventBody::Ack)).await;
+    let second_ack = next_event(&mut second_client_a, |body| matches!(body, EventBody::Ack)).await;
+    assert_eq!(first_ack.id, second_ack.id);
+    assert_eq!(next_message(&mut client_b).await, "once");
+    // 重複的訊息若被廣播，會在這則訊息之前收到
+    send_message(&mut client_b, "after once").await;
+}
@@ -0,0 +1,56 @@
+use base_library::new_uuid_v1;
+use chat::fanout::Fanout;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Barrier;
+use tokio::time::timeout;
+
+/// 同時加入的連線數
+const CONNECTIONS: usize = 5000;
+
+/// 聊天室數量（讓多個連線同時搶同一個聊天室）
+const ROOMS: usize = 20;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn thousands_of_concurrent_joins() {
+    let fanout = Fanout::local();
+    let rooms: Vec<String> = (0..ROOMS).map(|_| new_uuid_v1().to_string()).collect();
+    let joined = Arc::new(Barrier::new(CONNECTIONS));
+
+    let tasks: Vec<_> = (0..CONNECTIONS)
+        .map(|index| {
+            let fanout = fanout.clone();
+            let chatroom_uuid = rooms[index % ROOMS].clone();
+            let joined = joined.clone();
+            tokio::spawn(async move {
+                let (membership, mut rx) = fanout.join(&chatroom_uuid, new_uuid_v1());
+                let expected = format!("message for {}", chatroom_uuid);
+                // 所有連線都加入後，每個聊天室由第一個連線發送一則訊息，確保每個連線都收得到
+                joined.wait().await;
+                if index < ROOMS {
+                    fanout.publish(&chatroom_uuid, expected.clone()).await;
+                }
+                let received = timeout(Duration::from_secs(10), rx.recv()).await;
+                drop(membership);
+                match received {
+                    Ok(Ok(event)) if event == expected => Ok(()),
+                    Ok(Ok(event)) => Err(format!("connection {} received {:?}", index, event)),
+                    Ok(Err(RecvError::Lagged(skipped))) => Err(format!(
+                        "connection {} lagged behind by {} events",
+                        index, skipped
+                    )),
+                    Ok(Err(RecvError::Closed)) => Err(format!("connection {} was closed", index)),
+                    Err(_) => Err(format!("connection {} timed out", index)),
+                }
+            })
+        })
+        .collect();
+
+    for task in tasks {
+        if let Err(error) = task.await.expect("Join task panicked.") {
+            panic!("{}", error);
+        }
+    }
+    assert_eq!(fanout.room_count(), 0);
+}
@@ -0,0 +1,191 @@
+use crate::auth::API_KEY_PREFIX;
+use crate::outbox::Channel;
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::{delete, get, put};
+use axum::{Extension, Json, Router};
+use base_library::permission::{ApiKeyDelete, ApiKeyRead, ApiKeyWrite, RequirePermission};
+use base_library::token::{hash_token, new_opaque_token};
+use base_library::{
+    default_fallback, err_json_gen, get_db_err, new_uuid_v1, now_local_time, CustomJsonRequest,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+pub fn router() -> Router {
+    Router::new().nest(
+        "/apiKey",
+        Router::new()
+            .route("/list", get(list))
+            .route("/save", put(save))
+            .route("/delete/:id", delete(remove))
+            .fallback(default_fallback),
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, sqlx::FromRow)]
+struct ApiKey {
+    id: Uuid,
+    key_name: String,
+    #[serde(skip_serializing)]
+    key_hash: String,
+    scopes: Vec<String>,
+    key_status: bool,
+    #[serde(with = "time::serde::iso8601::option")]
+    last_used_timestamp: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::iso8601")]
+    creation_timestamp: OffsetDateTime,
+    #[serde(with = "time::serde::iso8601")]
+    update_timestamp: OffsetDateTime,
+}
+
+#[derive(Deserialize)]
+struct ApiKeyReq {
+    id: Option<Uuid>,
+    key_name: String,
+    scopes: Vec<Channel>,
+    #[serde(default = "default_key_status")]
+    key_status: bool,
+}
+
+fn default_key_status() -> bool {
+    true
+}
+
+/// 產生API Key（前綴加上256位元亂數的十六進位字串）
+fn new_api_key() -> String {
+    format!("{}{}", API_KEY_PREFIX, new_opaque_token())
+}
+
+/// 查詢所有API Key（不包含金鑰本身）
+async fn list(
+    RequirePermission(_, _): RequirePermission<ApiKeyRead>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+) -> impl IntoResponse {
+    match sqlx::query_as!(
+        ApiKey,
+        "select * from backendmodulesdb.notification_api_key order by creation_timestamp;"
+    )
+    .fetch_all(db)
+    .await
+    {
+        Ok(result) => Ok(Json::from(json!(result))),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 儲存API Key（有提供ID的情況更新名稱、scopes與狀態，無則新增），新增時回傳的api_key只會出現這一次
+async fn save(
+    RequirePermission(_, _): RequirePermission<ApiKeyWrite>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(params): CustomJsonRequest<ApiKeyReq>,
+) -> impl IntoResponse {
+    if params.key_name.trim().is_empty() || params.key_name.len() > 64 {
+        return Err(err_json_gen(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some("key_name must contain 1 to 64 bytes.".to_string()),
+        ));
+    }
+    if params.scopes.is_empty() {
+        return Err(err_json_gen(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some("scopes must contain at least one channel.".to_string()),
+        ));
+    }
+    let mut scopes: Vec<String> = params
+        .scopes
+        .iter()
+        .map(|channel| channel.as_str().to_string())
+        .collect();
+    scopes.sort();
+    scopes.dedup();
+    match params.id {
+        None => {
+            let api_key = new_api_key();
+            match sqlx::query_as!(
+                ApiKey,
+                r#"
+                insert into backendmodulesdb.notification_api_key (
+                    id,
+                    key_name,
+                    key_hash,
+                    scopes,
+                    key_status,
+                    creation_timestamp,
+                    update_timestamp
+                )
+                values (
+                    $1,
+                    $2,
+                    $3,
+                    $4,
+                    $5,
+                    $6,
+                    $6
+                ) returning *;
+                "#,
+                new_uuid_v1(),
+                params.key_name,
+                hash_token(&api_key),
+                &scopes,
+                params.key_status,
+                now_local_time()
+            )
+            .fetch_one(db)
+            .await
+            {
+                Ok(result) => {
+                    let mut response = json!(result);
+                    response["api_key"] = json!(api_key);
+                    Ok(Json::from(response))
+                }
+                Err(error) => Err(get_db_err(error)),
+            }
+        }
+        Some(id) => match sqlx::query_as!(
+            ApiKey,
+            r#"
+            update backendmodulesdb.notification_api_key
+            set key_name = $2,
+                scopes = $3,
+                key_status = $4,
+                update_timestamp = $5
+            where id = $1 returning *;
+            "#,
+            id,
+            params.key_name,
+            &scopes,
+            params.key_status,
+            now_local_time()
+        )
+        .fetch_one(db)
+        .await
+        {
+            Ok(result) => Ok(Json::from(json!(result))),
+            Err(error) => Err(get_db_err(error)),
+        },
+    }
+}
+
+/// 移除API Key，使用該金鑰的服務會立即無法呼叫API
+async fn remove(
+    RequirePermission(_, _): RequirePermission<ApiKeyDelete>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match sqlx::query_as!(
+        ApiKey,
+        "delete from backendmodulesdb.notification_api_key where id = $1 returning *;",
+        id
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(api_key) => Ok((StatusCode::OK, api_key.id.to_string())),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
@@ -0,0 +1,107 @@
+use a2::{Client, ClientConfig, Endpoint};
+use base_library::config::{ApnsConfig, ApnsEndpoint};
+use std::io::Read;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// 檢查金鑰檔案是否變更的間隔
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 共用的APNs client，金鑰檔案變更時自動重新建立
+pub struct ApnsClient {
+    key_path: String,
+    key_id: String,
+    team_id: String,
+    endpoint: Endpoint,
+    client: RwLock<Arc<Client>>,
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl ApnsClient {
+    /// 依設定建立APNs client，未設定APNs時回傳None（停用推播功能），金鑰無法讀取或格式錯誤時回傳錯誤
+    pub fn from_config(config: &ApnsConfig) -> Result<Option<Arc<ApnsClient>>, String> {
+        let (key_path, key_id, team_id) = match (&config.key_dir, &config.key_id, &config.team_id) {
+            (Some(key_path), Some(key_id), Some(team_id)) => {
+                (key_path.clone(), key_id.clone(), team_id.clone())
+            }
+            _ => {
+                info!("APNs key directory, KEY_ID and TEAM_ID not set, APNs related function will be disabled.");
+                return Ok(None);
+            }
+        };
+        let endpoint = match config.endpoint {
+            ApnsEndpoint::Production => Endpoint::Production,
+            ApnsEndpoint::Sandbox => Endpoint::Sandbox,
+        };
+        let modified = modified_time(&key_path);
+        let key = std::fs::read(&key_path)
+            .map_err(|error| format!("Unable to read APNs key {}: {}", key_path, error))?;
+        let client = build_client(&key[..], &key_id, &team_id, endpoint)?;
+        let apns_client = Arc::new(ApnsClient {
+            key_path,
+            key_id,
+            team_id,
+            endpoint,
+            client: RwLock::new(Arc::new(client)),
+            modified: Mutex::new(modified),
+        });
+        tokio::spawn(watch(Arc::downgrade(&apns_client)));
+        Ok(Some(apns_client))
+    }
+
+    /// 目前使用的client
+    pub fn client(&self) -> Arc<Client> {
+        self.client.read().unwrap().clone()
+    }
+
+    /// 金鑰檔案變更時重新建立client，失敗時保留原本的client
+    async fn reload_if_changed(&self) {
+        let modified = modified_time(&self.key_path);
+        if modified.is_none() || modified == *self.modified.lock().unwrap() {
+            return;
+        }
+        let result = match tokio::fs::read(&self.key_path).await {
+            Ok(key) => build_client(&key[..], &self.key_id, &self.team_id, self.endpoint),
+            Err(error) => Err(format!(
+                "Unable to read APNs key {}: {}",
+                self.key_path, error
+            )),
+        };
+        match result {
+            Ok(client) => {
+                *self.client.write().unwrap() = Arc::new(client);
+                *self.modified.lock().unwrap() = modified;
+                info!("APNs key {} changed, client reloaded.", self.key_path);
+            }
+            Err(error) => warn!("{}, keeping the previous APNs client.", error),
+        }
+    }
+}
+
+fn build_client<R: Read>(
+    key: R,
+    key_id: &str,
+    team_id: &str,
+    endpoint: Endpoint,
+) -> Result<Client, String> {
+    Client::token(key, key_id, team_id, ClientConfig::new(endpoint))
+        .map_err(|error| format!("Unable to create APNs client: {}", error))
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// 定期檢查金鑰檔案，ApnsClient釋放後結束
+async fn watch(apns_client: Weak<ApnsClient>) {
+    loop {
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+        match apns_client.upgrade() {
+            Some(apns_client) => apns_client.reload_if_changed().await,
+            None => return,
+        }
+    }
+}
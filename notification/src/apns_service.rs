@@ -1,35 +1,704 @@
-use crate::ApnsClientParam;
-use a2::{Client, NotificationBuilder, PlainNotificationBuilder};
+use crate::apns_client::ApnsClient;
+use crate::auth::{ApnsScope, Authorized};
+use crate::device_service::{self, DeviceToken};
+use crate::outbox::{batch_response, Channel, Delivered, DeliveryError, Outbox, Scheduled};
+use crate::template_service::{merge_variables, render_text};
+use a2::{CollapseId, ErrorReason, NotificationOptions, PayloadLike, Priority, PushType};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::{Extension, Json};
-use base_library::{err_json_gen, CustomJsonRequest};
-use serde::Deserialize;
-use serde_json::json;
-use tokio::fs::File;
-use tokio::task;
+use base_library::config::NotificationConfig;
+use base_library::{err_json_gen, get_db_err, CustomJsonRequest};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
 
 // Badge count must be processed in iOS app (We don't really want to store all user's badge count on backend service), see https://stackoverflow.com/a/53159748 for instructions.
-#[derive(Deserialize)]
+/// 推播通知請求，content為舊欄位（等同alert.body），只有data與content_available時為靜默推播
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ApnsNotificationRequest {
+    #[serde(default)]
     device_token: String,
-    content: String,
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    alert: Option<ApnsAlert>,
+    #[serde(default)]
+    sound: Option<String>,
+    #[serde(default)]
+    badge: Option<u32>,
+    #[serde(default)]
+    thread_id: Option<String>,
+    #[serde(default)]
+    category: Option<String>,
+    #[serde(default)]
+    mutable_content: bool,
+    #[serde(default)]
+    content_available: bool,
+    /// 自訂資料，每個key會放在payload的最上層（與aps同層）
+    #[serde(default)]
+    data: Map<String, Value>,
+    #[serde(default)]
+    priority: Option<ApnsPriority>,
+    /// 通知的有效期限（UNIX時間戳，秒），0表示只嘗試送達一次
+    #[serde(default)]
+    expiration: Option<u64>,
+    #[serde(default)]
+    collapse_id: Option<String>,
+    #[serde(default)]
+    push_type: Option<ApnsPushType>,
+    /// App的Bundle ID
+    #[serde(default)]
+    topic: Option<String>,
+}
+
+/// 通知內容，*_loc_key與*_loc_args對應App內Localizable.strings的key與參數
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+struct ApnsAlert {
+    title: Option<String>,
+    subtitle: Option<String>,
+    body: Option<String>,
+    title_loc_key: Option<String>,
+    title_loc_args: Vec<String>,
+    loc_key: Option<String>,
+    loc_args: Vec<String>,
+    action_loc_key: Option<String>,
+    launch_image: Option<String>,
+}
+
+/// 發送給用戶所有裝置的推播通知，內容欄位與ApnsNotificationRequest相同（不需要device_token）
+#[derive(Deserialize)]
+pub struct UserNotificationRequest {
+    user_uuid: Uuid,
+    #[serde(flatten)]
+    notification: ApnsNotificationRequest,
+}
+
+/// 批次推播請求，content、alert.title與alert.body可使用範本語法，套用每位收件人的變數
+#[derive(Deserialize)]
+pub struct BatchUserNotificationRequest {
+    recipients: Vec<BatchNotificationRecipient>,
+    /// 所有收件人共用的變數
+    #[serde(default)]
+    variables: Value,
+    #[serde(flatten)]
+    notification: ApnsNotificationRequest,
+}
+
+/// 批次推播的收件人，variables與共用的變數合併
+#[derive(Deserialize)]
+struct BatchNotificationRecipient {
+    user_uuid: Uuid,
+    #[serde(default)]
+    variables: Value,
+}
+
+/// 發送給訂閱主題的所有用戶的推播通知
+#[derive(Deserialize)]
+pub struct TopicNotificationRequest {
+    topic_name: String,
+    #[serde(flatten)]
+    notification: ApnsNotificationRequest,
+}
+
+impl ApnsNotificationRequest {
+    /// 將content、alert.title與alert.body套用變數
+    fn personalize(&self, variables: &Value) -> Result<ApnsNotificationRequest, String> {
+        let render = |name: &str, text: &Option<String>| {
+            text.as_deref()
+                .map(|text| render_text(name, text, variables))
+                .transpose()
+        };
+        let mut request = self.clone();
+        request.content = render("content", &self.content)?;
+        if let Some(alert) = &mut request.alert {
+            alert.title = render("alert.title", &alert.title)?;
+            alert.body = render("alert.body", &alert.body)?;
+        }
+        Ok(request)
+    }
+
+    /// 發送到指定裝置的通知，未指定topic時使用裝置登記的Bundle ID
+    fn for_device(&self, device: &DeviceToken) -> ApnsNotificationRequest {
+        ApnsNotificationRequest {
+            device_token: device.device_token.clone(),
+            topic: self
+                .topic
+                .clone()
+                .or_else(|| Some(device.bundle_id.clone())),
+            ..self.clone()
+        }
+    }
+}
+
+/// 未提供alert時使用
+static NO_ALERT: ApnsAlert = ApnsAlert {
+    title: None,
+    subtitle: None,
+    body: None,
+    title_loc_key: None,
+    title_loc_args: Vec::new(),
+    loc_key: None,
+    loc_args: Vec::new(),
+    action_loc_key: None,
+    launch_image: None,
+};
+
+impl ApnsAlert {
+    /// alert需要以字典表示（標題、副標題或在地化欄位），否則只有內文時以字串表示
+    fn is_dictionary(&self) -> bool {
+        self.title.is_some()
+            || self.subtitle.is_some()
+            || self.title_loc_key.is_some()
+            || self.loc_key.is_some()
+            || self.action_loc_key.is_some()
+            || self.launch_image.is_some()
+            || !self.title_loc_args.is_empty()
+            || !self.loc_args.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ApnsPriority {
+    High,
+    Normal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ApnsPushType {
+    Alert,
+    Background,
+    Location,
+    Voip,
+    Complication,
+    FileProvider,
+    Mdm,
+}
+
+/// APNs payload，自訂資料放在最上層（與aps同層）
+#[derive(Serialize, Debug)]
+struct ApnsPayload<'a> {
+    #[serde(skip)]
+    options: NotificationOptions<'a>,
+    #[serde(skip)]
+    device_token: &'a str,
+    aps: Map<String, Value>,
+    #[serde(flatten)]
+    data: &'a Map<String, Value>,
+}
+
+impl PayloadLike for ApnsPayload<'_> {
+    fn get_device_token(&self) -> &str {
+        self.device_token
+    }
+
+    fn get_options(&self) -> &NotificationOptions<'_> {
+        &self.options
+    }
+}
+
+/// 建立APNs payload與標頭（存入佇列前先驗證一次，worker發送時再建立一次），錯誤訊息會指出欄位
+fn build_payload(request: &ApnsNotificationRequest) -> Result<ApnsPayload<'_>, Vec<String>> {
+    let mut errors = Vec::new();
+    if request.device_token.is_empty()
+        || !request
+            .device_token
+            .chars()
+            .all(|char| char.is_ascii_hexdigit())
+    {
+        errors.push("device_token must be a hexadecimal string.".to_string());
+    }
+    let alert = request.alert.as_ref().unwrap_or(&NO_ALERT);
+    if request.content.is_some() && alert.body.is_some() {
+        errors.push("content and alert.body must not be used together.".to_string());
+    }
+    let body = request.content.as_deref().or(alert.body.as_deref());
+    let has_alert = body.is_some() || alert.is_dictionary();
+    if !has_alert
+        && !request.content_available
+        && request.badge.is_none()
+        && request.sound.is_none()
+    {
+        errors.push(
+            "Either content, alert, badge, sound or content_available must be provided."
+                .to_string(),
+        );
+    }
+    if request.data.contains_key("aps") {
+        errors.push("data must not contain the reserved aps key.".to_string());
+    }
+    if request.push_type == Some(ApnsPushType::Background) {
+        if has_alert || request.sound.is_some() || request.badge.is_some() {
+            errors.push("push_type background must not contain alert, sound or badge.".to_string());
+        }
+        if request.priority == Some(ApnsPriority::High) {
+            errors.push("push_type background requires priority normal.".to_string());
+        }
+    }
+    let collapse_id = match request.collapse_id.as_deref().map(CollapseId::new) {
+        Some(Ok(collapse_id)) => Some(collapse_id),
+        Some(Err(_)) => {
+            errors.push("collapse_id must not exceed 64 bytes.".to_string());
+            None
+        }
+        None => None,
+    };
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let options = NotificationOptions {
+        apns_id: None,
+        apns_expiration: request.expiration,
+        apns_priority: request.priority.map(|priority| match priority {
+            ApnsPriority::High => Priority::High,
+            ApnsPriority::Normal => Priority::Normal,
+        }),
+        apns_topic: request.topic.as_deref(),
+        apns_collapse_id: collapse_id,
+        apns_push_type: request.push_type.map(|push_type| match push_type {
+            ApnsPushType::Alert => PushType::Alert,
+            ApnsPushType::Background => PushType::Background,
+            ApnsPushType::Location => PushType::Location,
+            ApnsPushType::Voip => PushType::Voip,
+            ApnsPushType::Complication => PushType::Complication,
+            ApnsPushType::FileProvider => PushType::FileProvider,
+            ApnsPushType::Mdm => PushType::Mdm,
+        }),
+    };
+    let mut aps = Map::new();
+    if alert.is_dictionary() {
+        let mut fields = Map::new();
+        let mut insert = |key: &str, value: Option<&str>| {
+            if let Some(value) = value {
+                fields.insert(key.to_string(), json!(value));
+            }
+        };
+        insert("title", alert.title.as_deref());
+        insert("subtitle", alert.subtitle.as_deref());
+        insert("body", body);
+        insert("title-loc-key", alert.title_loc_key.as_deref());
+        insert("loc-key", alert.loc_key.as_deref());
+        insert("action-loc-key", alert.action_loc_key.as_deref());
+        insert("launch-image", alert.launch_image.as_deref());
+        if !alert.title_loc_args.is_empty() {
+            fields.insert("title-loc-args".to_string(), json!(alert.title_loc_args));
+        }
+        if !alert.loc_args.is_empty() {
+            fields.insert("loc-args".to_string(), json!(alert.loc_args));
+        }
+        aps.insert("alert".to_string(), Value::Object(fields));
+    } else if let Some(body) = body {
+        aps.insert("alert".to_string(), json!(body));
+    }
+    if let Some(badge) = request.badge {
+        aps.insert("badge".to_string(), json!(badge));
+    }
+    if let Some(sound) = &request.sound {
+        aps.insert("sound".to_string(), json!(sound));
+    }
+    if let Some(thread_id) = &request.thread_id {
+        aps.insert("thread-id".to_string(), json!(thread_id));
+    }
+    if let Some(category) = &request.category {
+        aps.insert("category".to_string(), json!(category));
+    }
+    // 一般通知也可同時喚醒App在背景處理
+    if request.content_available {
+        aps.insert("content-available".to_string(), json!(1));
+    }
+    if request.mutable_content && has_alert {
+        aps.insert("mutable-content".to_string(), json!(1));
+    }
+    Ok(ApnsPayload {
+        options,
+        device_token: &request.device_token,
+        aps,
+        data: &request.data,
+    })
 }
 
+/// 將推播通知存入通知佇列，回傳job ID
 pub async fn sent_apple_notification(
-    Extension(apns_client_params): Extension<ApnsClientParam>,
-    CustomJsonRequest(request): CustomJsonRequest<ApnsNotificationRequest>,
+    Authorized(caller, _): Authorized<ApnsScope>,
+    Extension(outbox): Extension<Arc<Outbox>>,
+    CustomJsonRequest(Scheduled { schedule, request }): CustomJsonRequest<
+        Scheduled<ApnsNotificationRequest>,
+    >,
+) -> impl IntoResponse {
+    let send_at = match schedule.resolve() {
+        Ok(send_at) => send_at,
+        Err(error) => return error,
+    };
+    if let Err(errors) = build_payload(&request) {
+        return err_json_gen(StatusCode::UNPROCESSABLE_ENTITY, Some(errors.join(" ")));
+    }
+    match outbox
+        .enqueue(&caller, Channel::Apns, &request, send_at)
+        .await
+    {
+        Ok(job_id) => (StatusCode::ACCEPTED, Json(json!({ "job_id": job_id }))),
+        Err(error) => error,
+    }
+}
+
+/// 將推播通知存入通知佇列（用戶登記的每個裝置各一筆，全部存入或全部失敗），回傳各裝置的job ID
+pub async fn sent_user_notification(
+    Authorized(caller, _): Authorized<ApnsScope>,
+    Extension(outbox): Extension<Arc<Outbox>>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(Scheduled { schedule, request }): CustomJsonRequest<
+        Scheduled<UserNotificationRequest>,
+    >,
+) -> impl IntoResponse {
+    let send_at = match schedule.resolve() {
+        Ok(send_at) => send_at,
+        Err(error) => return error,
+    };
+    let devices = match device_service::user_devices(db, request.user_uuid).await {
+        Ok(devices) => devices,
+        Err(error) => return get_db_err(error),
+    };
+    if devices.is_empty() {
+        return err_json_gen(
+            StatusCode::NOT_FOUND,
+            Some("This user has no registered devices.".to_string()),
+        );
+    }
+    let notifications: Vec<ApnsNotificationRequest> = devices
+        .iter()
+        .map(|device| request.notification.for_device(device))
+        .collect();
+    for notification in &notifications {
+        if let Err(errors) = build_payload(notification) {
+            return err_json_gen(StatusCode::UNPROCESSABLE_ENTITY, Some(errors.join(" ")));
+        }
+    }
+    // 所有裝置在同一個交易中存入佇列，失敗時不會留下部分裝置的通知
+    let job_ids = match outbox
+        .enqueue_many(&caller, Channel::Apns, &notifications, send_at)
+        .await
+    {
+        Ok(job_ids) => job_ids,
+        Err(error) => return error,
+    };
+    let jobs: Vec<Value> = notifications
+        .iter()
+        .zip(job_ids)
+        .map(|(notification, job_id)| {
+            json!({
+                "device_token": notification.device_token,
+                "job_id": job_id
+            })
+        })
+        .collect();
+    (StatusCode::ACCEPTED, Json(json!({ "jobs": jobs })))
+}
+
+/// 批次推播給多位用戶的所有裝置，逐一套用每位收件人的變數後一併存入通知佇列
+///
+/// 沒有登記裝置或內容有誤的收件人會被拒絕，其餘收件人照常發送
+pub async fn sent_batch_user_notification(
+    Authorized(caller, _): Authorized<ApnsScope>,
+    Extension(outbox): Extension<Arc<Outbox>>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Extension(config): Extension<NotificationConfig>,
+    CustomJsonRequest(Scheduled { schedule, request }): CustomJsonRequest<
+        Scheduled<BatchUserNotificationRequest>,
+    >,
 ) -> impl IntoResponse {
-    match File::open(apns_client_params.pkcs8_pem_dir).await {
-        Ok(file) => {
-            let client = Client::token(
-                file.into_std().await,
-                apns_client_params.key_id,
-                apns_client_params.team_id,
-                apns_client_params.endpoint).unwrap();
-            task::spawn(async move { client.send(PlainNotificationBuilder::new(&request.content).build(&request.device_token, Default::default())).await });
-            (StatusCode::OK, Json(json!("{'description', 'APN has been sent.'}")))
-        }
-        Err(error) => err_json_gen(StatusCode::INTERNAL_SERVER_ERROR, Some(format!("Error when accessing APNs token, please inform system administrator about this error. Error returned from library: {}", error)))
+    let send_at = match schedule.resolve() {
+        Ok(send_at) => send_at,
+        Err(error) => return error,
+    };
+    if request.recipients.is_empty() || request.recipients.len() > config.batch_limit {
+        return err_json_gen(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some(format!(
+                "recipients must contain 1 to {} entries.",
+                config.batch_limit
+            )),
+        );
+    }
+    let user_uuids: Vec<Uuid> = request
+        .recipients
+        .iter()
+        .map(|recipient| recipient.user_uuid)
+        .collect();
+    let mut devices: HashMap<Uuid, Vec<DeviceToken>> = HashMap::new();
+    match device_service::users_devices(db, &user_uuids).await {
+        Ok(result) => {
+            for device in result {
+                devices.entry(device.user_uuid).or_default().push(device);
+            }
+        }
+        Err(error) => return get_db_err(error),
+    }
+
+    // results先記錄被接受的收件人在notifications中的範圍，存入佇列後再換成job ID
+    let mut results = Vec::with_capacity(request.recipients.len());
+    let mut notifications = Vec::new();
+    for recipient in &request.recipients {
+        let user_devices = match devices.get(&recipient.user_uuid) {
+            Some(user_devices) => user_devices,
+            None => {
+                results.push(Err("This user has no registered devices.".to_string()));
+                continue;
+            }
+        };
+        let variables = merge_variables(&request.variables, &recipient.variables);
+        let personalized = match request.notification.personalize(&variables) {
+            Ok(personalized) => personalized,
+            Err(reason) => {
+                results.push(Err(reason));
+                continue;
+            }
+        };
+        let user_notifications: Vec<ApnsNotificationRequest> = user_devices
+            .iter()
+            .map(|device| personalized.for_device(device))
+            .collect();
+        match user_notifications
+            .iter()
+            .find_map(|notification| build_payload(notification).err())
+        {
+            Some(errors) => results.push(Err(errors.join(" "))),
+            None => {
+                results.push(Ok(
+                    notifications.len()..notifications.len() + user_notifications.len()
+                ));
+                notifications.extend(user_notifications);
+            }
+        }
+    }
+
+    let job_ids = match outbox
+        .enqueue_many(&caller, Channel::Apns, &notifications, send_at)
+        .await
+    {
+        Ok(job_ids) => job_ids,
+        Err(error) => return error,
+    };
+    batch_response(
+        results
+            .into_iter()
+            .zip(&request.recipients)
+            .enumerate()
+            .map(|(index, (result, recipient))| match result {
+                Ok(range) => json!({
+                    "index": index,
+                    "user_uuid": recipient.user_uuid,
+                    "accepted": true,
+                    "jobs": range
+                        .map(|position| json!({
+                            "device_token": notifications[position].device_token,
+                            "job_id": job_ids[position]
+                        }))
+                        .collect::<Vec<Value>>()
+                }),
+                Err(reason) => json!({
+                    "index": index,
+                    "user_uuid": recipient.user_uuid,
+                    "accepted": false,
+                    "reason": reason
+                }),
+            })
+            .collect(),
+    )
+}
+
+/// 推播給訂閱主題的所有用戶的所有裝置，回傳各裝置的結果
+pub async fn sent_topic_notification(
+    Authorized(caller, _): Authorized<ApnsScope>,
+    Extension(outbox): Extension<Arc<Outbox>>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(Scheduled { schedule, request }): CustomJsonRequest<
+        Scheduled<TopicNotificationRequest>,
+    >,
+) -> impl IntoResponse {
+    let send_at = match schedule.resolve() {
+        Ok(send_at) => send_at,
+        Err(error) => return error,
+    };
+    match sqlx::query!(
+        "select topic_name from backendmodulesdb.notification_topic where topic_name = $1;",
+        request.topic_name
+    )
+    .fetch_optional(db)
+    .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return err_json_gen(StatusCode::NOT_FOUND, Some("Topic not found.".to_string()))
+        }
+        Err(error) => return get_db_err(error),
+    }
+    let devices = match device_service::topic_devices(db, &request.topic_name).await {
+        Ok(devices) => devices,
+        Err(error) => return get_db_err(error),
+    };
+
+    let mut results = Vec::with_capacity(devices.len());
+    let mut notifications = Vec::new();
+    for device in &devices {
+        let notification = request.notification.for_device(device);
+        match build_payload(&notification) {
+            Ok(_) => {
+                results.push(Ok(notifications.len()));
+                notifications.push(notification);
+            }
+            Err(errors) => results.push(Err(errors.join(" "))),
+        }
+    }
+
+    let job_ids = match outbox
+        .enqueue_many(&caller, Channel::Apns, &notifications, send_at)
+        .await
+    {
+        Ok(job_ids) => job_ids,
+        Err(error) => return error,
+    };
+    batch_response(
+        results
+            .into_iter()
+            .zip(&devices)
+            .map(|(result, device)| match result {
+                Ok(position) => json!({
+                    "device_token": device.device_token,
+                    "accepted": true,
+                    "job_id": job_ids[position]
+                }),
+                Err(reason) => json!({
+                    "device_token": device.device_token,
+                    "accepted": false,
+                    "reason": reason
+                }),
+            })
+            .collect(),
+    )
+}
+
+/// 發送推播通知（由worker呼叫），APNs回覆4xx（429除外）視為永久失敗，裝置Token失效時從裝置登記中刪除
+pub async fn deliver(
+    apns_client: &ApnsClient,
+    db: &Pool<Postgres>,
+    request: ApnsNotificationRequest,
+) -> Result<Delivered, DeliveryError> {
+    let payload = build_payload(&request).map_err(|errors| DeliveryError {
+        permanent: true,
+        code: None,
+        reason: errors.join(" "),
+    })?;
+    match apns_client.client().send(payload).await {
+        Ok(response) => Ok(Delivered {
+            code: Some(response.code.to_string()),
+            detail: response.apns_id,
+        }),
+        Err(a2::Error::ResponseError(response)) => {
+            let invalid_token = matches!(
+                response.error.as_ref().map(|error| &error.reason),
+                Some(ErrorReason::Unregistered) | Some(ErrorReason::BadDeviceToken)
+            );
+            if invalid_token {
+                if let Err(error) = device_service::prune(db, &request.device_token).await {
+                    tracing::warn!("Unable to remove device token: {}", error);
+                }
+            }
+            Err(DeliveryError {
+                permanent: (400..500).contains(&response.code) && response.code != 429,
+                code: Some(response.code.to_string()),
+                reason: response
+                    .error
+                    .map(|error| format!("{:?}", error.reason))
+                    .unwrap_or_else(|| "APNs rejected the notification.".to_string()),
+            })
+        }
+        Err(error) => Err(DeliveryError {
+            permanent: false,
+            code: None,
+            reason: error.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(value: Value) -> ApnsNotificationRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn serialize(request: &ApnsNotificationRequest) -> String {
+        build_payload(request).unwrap().to_json_string().unwrap()
+    }
+
+    #[test]
+    fn payload_has_a_single_aps_with_subtitle_and_thread_id() {
+        let request = request(json!({
+            "device_token": "abcdef0123",
+            "content": "body",
+            "alert": { "subtitle": "subtitle" },
+            "thread_id": "thread",
+            "sound": "default",
+            "data": { "order_id": 1 }
+        }));
+        let json = serialize(&request);
+        assert_eq!(json.matches("\"aps\"").count(), 1, "{}", json);
+        let payload: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            payload,
+            json!({
+                "aps": {
+                    "alert": { "subtitle": "subtitle", "body": "body" },
+                    "sound": "default",
+                    "thread-id": "thread"
+                },
+                "order_id": 1
+            })
+        );
+    }
+
+    #[test]
+    fn body_only_alert_is_a_string() {
+        let request = request(json!({
+            "device_token": "abcdef0123",
+            "content": "body",
+            "badge": 3
+        }));
+        let payload: Value = serde_json::from_str(&serialize(&request)).unwrap();
+        assert_eq!(payload, json!({ "aps": { "alert": "body", "badge": 3 } }));
+    }
+
+    #[test]
+    fn silent_notification_has_no_alert() {
+        let request = request(json!({
+            "device_token": "abcdef0123",
+            "content_available": true,
+            "mutable_content": true,
+            "data": { "sync": true }
+        }));
+        let payload: Value = serde_json::from_str(&serialize(&request)).unwrap();
+        assert_eq!(
+            payload,
+            json!({ "aps": { "content-available": 1 }, "sync": true })
+        );
+    }
+
+    #[test]
+    fn data_must_not_contain_aps() {
+        let request = request(json!({
+            "device_token": "abcdef0123",
+            "content": "body",
+            "data": { "aps": {} }
+        }));
+        let errors = build_payload(&request).unwrap_err();
+        assert_eq!(errors, ["data must not contain the reserved aps key."]);
     }
 }
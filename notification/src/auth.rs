@@ -0,0 +1,129 @@
+use crate::outbox::Channel;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::{async_trait, Extension, Json};
+use base_library::token::hash_token;
+use base_library::{err_json_gen, get_db_err, AdminToken, Claims};
+use serde_json::Value;
+use sqlx::{Pool, Postgres};
+use std::marker::PhantomData;
+use uuid::Uuid;
+
+/// 傳送API Key的標頭
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// API Key的前綴，方便辨識外洩的金鑰
+pub const API_KEY_PREFIX: &str = "nk_";
+
+/// 呼叫API需要的通知管道，CHANNEL為None時任一管道皆可
+pub trait Scope: Send + Sync + 'static {
+    const CHANNEL: Option<Channel>;
+}
+
+pub struct MailScope;
+
+impl Scope for MailScope {
+    const CHANNEL: Option<Channel> = Some(Channel::Mail);
+}
+
+pub struct ApnsScope;
+
+impl Scope for ApnsScope {
+    const CHANNEL: Option<Channel> = Some(Channel::Apns);
+}
+
+pub struct AnyScope;
+
+impl Scope for AnyScope {
+    const CHANNEL: Option<Channel> = None;
+}
+
+/// 呼叫發送通知API的身分（API Key附上可使用的通知管道）
+pub enum Caller {
+    Admin(Claims),
+    ApiKey { id: Uuid, scopes: Vec<String> },
+}
+
+impl Caller {
+    /// 建立通知時記錄的呼叫者：(API Key ID, 管理員UUID)
+    pub fn creator(&self) -> (Option<Uuid>, Option<Uuid>) {
+        match self {
+            Caller::Admin(claims) => (None, Some(claims.uuid)),
+            Caller::ApiKey { id, .. } => (Some(*id), None),
+        }
+    }
+}
+
+/// 驗證管理員的Access Token，或`X-Api-Key`標頭的API Key（需擁有S對應管道的scope），失敗時回傳401或403
+///
+/// 用法：`Authorized(caller, _): Authorized<MailScope>`
+pub struct Authorized<S: Scope>(pub Caller, pub PhantomData<S>);
+
+#[async_trait]
+impl<S, P> FromRequestParts<S> for Authorized<P>
+where
+    S: Send + Sync,
+    P: Scope,
+{
+    type Rejection = (StatusCode, Json<Value>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let api_key = match parts.headers.get(API_KEY_HEADER) {
+            Some(api_key) => api_key.to_str().unwrap_or_default().to_string(),
+            None => {
+                let AdminToken(claims) = AdminToken::from_request_parts(parts, state).await?;
+                return Ok(Self(Caller::Admin(claims), PhantomData));
+            }
+        };
+        let Extension(db) = Extension::<Pool<Postgres>>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| {
+                err_json_gen(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Some("API keys are unavailable on this server.".to_string()),
+                )
+            })?;
+        let record = sqlx::query!(
+            r#"
+            update backendmodulesdb.notification_api_key
+            set last_used_timestamp = now()
+            where key_hash = $1 and key_status = true
+            returning id, scopes;
+            "#,
+            hash_token(&api_key)
+        )
+        .fetch_optional(&db)
+        .await
+        .map_err(get_db_err)?
+        .ok_or_else(|| {
+            err_json_gen(
+                StatusCode::UNAUTHORIZED,
+                Some("API key is invalid or disabled.".to_string()),
+            )
+        })?;
+        let allowed = match P::CHANNEL {
+            Some(channel) => record.scopes.iter().any(|scope| scope == channel.as_str()),
+            None => !record.scopes.is_empty(),
+        };
+        if allowed {
+            Ok(Self(
+                Caller::ApiKey {
+                    id: record.id,
+                    scopes: record.scopes,
+                },
+                PhantomData,
+            ))
+        } else {
+            Err(err_json_gen(
+                StatusCode::FORBIDDEN,
+                Some(format!(
+                    "This API key doesn't have the \"{}\" scope required by this API.",
+                    P::CHANNEL
+                        .map(|channel| channel.as_str())
+                        .unwrap_or("mail or apns")
+                )),
+            ))
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::{delete, get, put};
+use axum::{Extension, Json, Router};
+use base_library::{
+    default_fallback, err_json_gen, get_db_err, now_local_time, CustomJsonRequest, UserToken,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+pub fn router() -> Router {
+    Router::new().nest(
+        "/device",
+        Router::new()
+            .route("/list", get(list))
+            .route("/register", put(register))
+            .route("/unregister/:device_token", delete(unregister))
+            .fallback(default_fallback),
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct DeviceToken {
+    pub device_token: String,
+    pub user_uuid: Uuid,
+    pub platform: String,
+    pub bundle_id: String,
+    pub locale: Option<String>,
+    #[serde(with = "time::serde::iso8601")]
+    pub creation_timestamp: OffsetDateTime,
+    #[serde(with = "time::serde::iso8601")]
+    pub update_timestamp: OffsetDateTime,
+}
+
+/// 透過APNs推播的平台
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Platform {
+    Ios,
+    Macos,
+    Watchos,
+    Tvos,
+}
+
+impl Platform {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Platform::Ios => "ios",
+            Platform::Macos => "macos",
+            Platform::Watchos => "watchos",
+            Platform::Tvos => "tvos",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RegisterReq {
+    device_token: String,
+    platform: Platform,
+    /// App的Bundle ID，推播時作為apns-topic
+    bundle_id: String,
+    locale: Option<String>,
+}
+
+/// 查詢目前用戶登記的裝置
+async fn list(
+    UserToken(claims): UserToken,
+    Extension(ref db): Extension<Pool<Postgres>>,
+) -> impl IntoResponse {
+    match user_devices(db, claims.uuid).await {
+        Ok(result) => Ok(Json::from(json!(result))),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 登記目前用戶的裝置（裝置Token已登記時改為屬於目前用戶並更新資訊）
+async fn register(
+    UserToken(claims): UserToken,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(params): CustomJsonRequest<RegisterReq>,
+) -> impl IntoResponse {
+    if params.device_token.is_empty()
+        || params.device_token.len() > 200
+        || !params
+            .device_token
+            .chars()
+            .all(|char| char.is_ascii_hexdigit())
+    {
+        return Err(err_json_gen(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some("device_token must be a hexadecimal string.".to_string()),
+        ));
+    }
+    if params.bundle_id.trim().is_empty() {
+        return Err(err_json_gen(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some("bundle_id must not be empty.".to_string()),
+        ));
+    }
+    match sqlx::query_as!(
+        DeviceToken,
+        r#"
+        insert into backendmodulesdb.device_token (
+            device_token,
+            user_uuid,
+            platform,
+            bundle_id,
+            locale,
+            creation_timestamp,
+            update_timestamp
+        )
+        values (
+            $1,
+            $2,
+            $3,
+            $4,
+            $5,
+            $6,
+            $6
+        )
+        on conflict (device_token) do update
+        set user_uuid = excluded.user_uuid,
+            platform = excluded.platform,
+            bundle_id = excluded.bundle_id,
+            locale = excluded.locale,
+            update_timestamp = excluded.update_timestamp
+        returning *;
+        "#,
+        params.device_token.to_ascii_lowercase(),
+        claims.uuid,
+        params.platform.as_str(),
+        params.bundle_id,
+        params.locale,
+        now_local_time()
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(result) => Ok(Json::from(json!(result))),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 取消登記目前用戶的裝置
+async fn unregister(
+    UserToken(claims): UserToken,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Path(device_token): Path<String>,
+) -> impl IntoResponse {
+    match sqlx::query_as!(
+        DeviceToken,
+        "delete from backendmodulesdb.device_token where device_token = $1 and user_uuid = $2 returning *;",
+        device_token.to_ascii_lowercase(),
+        claims.uuid
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(device) => Ok((StatusCode::OK, device.device_token)),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 查詢用戶登記的所有裝置
+pub async fn user_devices(
+    db: &Pool<Postgres>,
+    user_uuid: Uuid,
+) -> Result<Vec<DeviceToken>, sqlx::Error> {
+    sqlx::query_as!(
+        DeviceToken,
+        "select * from backendmodulesdb.device_token where user_uuid = $1 order by creation_timestamp;",
+        user_uuid
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// 查詢多位用戶登記的所有裝置（批次推播）
+pub async fn users_devices(
+    db: &Pool<Postgres>,
+    user_uuids: &[Uuid],
+) -> Result<Vec<DeviceToken>, sqlx::Error> {
+    sqlx::query_as!(
+        DeviceToken,
+        "select * from backendmodulesdb.device_token where user_uuid = any($1) order by creation_timestamp;",
+        user_uuids
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// 查詢訂閱主題的用戶登記的所有裝置
+pub async fn topic_devices(
+    db: &Pool<Postgres>,
+    topic_name: &str,
+) -> Result<Vec<DeviceToken>, sqlx::Error> {
+    sqlx::query_as!(
+        DeviceToken,
+        r#"
+        select device_token.* from backendmodulesdb.device_token device_token
+        join backendmodulesdb.topic_subscription subscription on subscription.user_uuid = device_token.user_uuid
+        where subscription.topic_name = $1
+        order by device_token.creation_timestamp;
+        "#,
+        topic_name
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// 刪除APNs回覆已失效的裝置Token
+pub async fn prune(db: &Pool<Postgres>, device_token: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "delete from backendmodulesdb.device_token where device_token = $1;",
+        device_token.to_ascii_lowercase()
+    )
+    .execute(db)
+    .await
+    .map(|_| ())
+}
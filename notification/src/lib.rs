@@ -0,0 +1,23 @@
+#![forbid(unsafe_code)]
+
+pub mod api_key_service;
+pub mod apns_client;
+pub mod apns_service;
+pub mod auth;
+pub mod device_service;
+pub mod mail_service;
+pub mod outbox;
+pub mod status_service;
+pub mod template_service;
+pub mod topic_service;
+pub mod transport;
+
+use apns_client::ApnsClient;
+use std::sync::Arc;
+use transport::MailTransport;
+
+/// worker發送通知時使用的各管道設定，未設定的管道為None
+pub struct Dispatcher {
+    pub mailer: Option<Arc<dyn MailTransport>>,
+    pub apns: Option<Arc<ApnsClient>>,
+}
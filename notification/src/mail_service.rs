@@ -1,61 +1,681 @@
+use crate::auth::{Authorized, MailScope};
+use crate::outbox::{batch_response, Channel, Delivered, DeliveryError, Outbox, Scheduled};
+use crate::template_service::{self, merge_variables, TemplateSet};
+use crate::transport::{MailTransport, MemoryMailTransport};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 
 use axum::{Extension, Json};
-use base_library::{err_json_gen, CustomJsonRequest};
-use lettre::message::Mailbox;
-use lettre::{Address, Message, SmtpTransport, Transport};
-use serde::Deserialize;
-use serde_json::json;
+use base_library::config::NotificationConfig;
+use base_library::permission::{NotificationRead, RequirePermission};
+use base_library::{err_json_gen, get_db_err, new_uuid_v1, CustomJsonRequest};
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MessageBuilder, MultiPart, SinglePart};
+use lettre::Message;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{Pool, Postgres};
 use std::str::FromStr;
-use tokio::task;
+use std::sync::Arc;
+use uuid::Uuid;
 
+/// 寄信請求，直接提供title與content（可另外提供html_content），或指定template（搭配locale與variables）
 #[derive(Deserialize)]
 pub struct MailRequest {
-    from_address: String,
-    to_address: String,
+    #[serde(flatten)]
+    envelope: Envelope,
+    title: Option<String>,
+    content: Option<String>,
+    html_content: Option<String>,
+    template: Option<String>,
+    locale: Option<String>,
+    #[serde(default)]
+    variables: Value,
+}
+
+/// 批次寄信請求，所有收件人共用寄件人、附件與範本，每位收件人的地址會取代共用的to、cc與bcc
+#[derive(Deserialize)]
+pub struct BatchMailRequest {
+    #[serde(flatten)]
+    envelope: Envelope,
+    template: String,
+    /// 所有收件人共用的變數
+    #[serde(default)]
+    variables: Value,
+    recipients: Vec<BatchMailRecipient>,
+}
+
+/// 批次寄信的收件人，variables與共用的變數合併
+#[derive(Deserialize)]
+struct BatchMailRecipient {
+    to_address: Addresses,
+    #[serde(default)]
+    cc_address: Addresses,
+    #[serde(default)]
+    bcc_address: Addresses,
+    #[serde(default)]
+    locale: Option<String>,
+    #[serde(default)]
+    variables: Value,
+}
+
+/// 存入通知佇列的郵件（範本已套用變數）
+#[derive(Serialize, Deserialize)]
+pub struct MailJob {
+    #[serde(flatten)]
+    envelope: Envelope,
     title: String,
     content: String,
+    #[serde(default)]
+    html_content: Option<String>,
+    #[serde(default)]
+    template: Option<String>,
+    #[serde(default)]
+    locale: Option<String>,
+    /// 批次寄信共用的附件與內嵌圖片（mail_attachment.id），發送時再讀取
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attachment_id: Option<Uuid>,
+}
+
+/// 寄件人、收件人與附件，地址可包含顯示名稱（如`王小明 <ming@example.com>`）
+#[derive(Serialize, Deserialize, Clone)]
+struct Envelope {
+    from_address: String,
+    #[serde(default)]
+    to_address: Addresses,
+    #[serde(default)]
+    cc_address: Addresses,
+    #[serde(default)]
+    bcc_address: Addresses,
+    #[serde(default)]
+    reply_to: Option<String>,
+    /// HTML內容以`cid:{content_id}`引用的圖片
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    inline_images: Vec<InlineImage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<MailAttachment>,
+}
+
+/// 單一地址或地址陣列
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(untagged)]
+enum Addresses {
+    #[default]
+    None,
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Addresses {
+    fn as_slice(&self) -> &[String] {
+        match self {
+            Addresses::None => &[],
+            Addresses::One(address) => std::slice::from_ref(address),
+            Addresses::Many(addresses) => addresses,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct InlineImage {
+    content_id: String,
+    content_type: String,
+    /// Base64編碼的內容
+    data: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct MailAttachment {
+    filename: String,
+    content_type: String,
+    /// Base64編碼的內容
+    data: String,
+}
+
+type AddRecipient = fn(MessageBuilder, Mailbox) -> MessageBuilder;
+
+/// 解析地址（包含顯示名稱）
+fn parse_mailbox(field: &str, address: &str, errors: &mut Vec<String>) -> Option<Mailbox> {
+    match Mailbox::from_str(address) {
+        Ok(mailbox) => Some(mailbox),
+        Err(err) => {
+            errors.push(format!("{} could not be parsed: {}.", field, err));
+            None
+        }
+    }
+}
+
+/// 解析附件的MIME類型與內容
+fn parse_part(
+    field: &str,
+    content_type: &str,
+    data: &str,
+    errors: &mut Vec<String>,
+) -> Option<(ContentType, Vec<u8>)> {
+    let content_type = ContentType::parse(content_type)
+        .map_err(|_| errors.push(format!("{}.content_type is not a valid MIME type.", field)))
+        .ok();
+    let data = base64::decode(data)
+        .map_err(|err| errors.push(format!("{}.data is not valid base64: {}.", field, err)))
+        .ok();
+    content_type.zip(data)
+}
+
+/// 加入收件人、副本與密件副本
+fn add_recipients(
+    mut builder: MessageBuilder,
+    envelope: &Envelope,
+    errors: &mut Vec<String>,
+) -> MessageBuilder {
+    if envelope.to_address.as_slice().is_empty() {
+        errors.push("to_address must contain at least one address.".to_string());
+    }
+    let recipients: [(&str, &Addresses, AddRecipient); 3] = [
+        ("to_address", &envelope.to_address, MessageBuilder::to),
+        ("cc_address", &envelope.cc_address, MessageBuilder::cc),
+        ("bcc_address", &envelope.bcc_address, MessageBuilder::bcc),
+    ];
+    for (field, addresses, add_recipient) in recipients {
+        for (index, address) in addresses.as_slice().iter().enumerate() {
+            let field = format!("{}[{}]", field, index);
+            if let Some(mailbox) = parse_mailbox(&field, address, errors) {
+                builder = add_recipient(builder, mailbox);
+            }
+        }
+    }
+    builder
+}
+
+/// 解析內嵌圖片與附件
+fn parse_parts(
+    envelope: &Envelope,
+    errors: &mut Vec<String>,
+) -> (Vec<SinglePart>, Vec<SinglePart>) {
+    let mut inline_images = Vec::new();
+    for (index, image) in envelope.inline_images.iter().enumerate() {
+        let field = format!("inline_images[{}]", index);
+        if let Some((content_type, data)) =
+            parse_part(&field, &image.content_type, &image.data, errors)
+        {
+            inline_images
+                .push(Attachment::new_inline(image.content_id.clone()).body(data, content_type));
+        }
+    }
+    let mut attachments = Vec::new();
+    for (index, attachment) in envelope.attachments.iter().enumerate() {
+        let field = format!("attachments[{}]", index);
+        if let Some((content_type, data)) =
+            parse_part(&field, &attachment.content_type, &attachment.data, errors)
+        {
+            attachments.push(Attachment::new(attachment.filename.clone()).body(data, content_type));
+        }
+    }
+    (inline_images, attachments)
+}
+
+/// 建立郵件內容（收件前先驗證一次，worker發送時再建立一次），有HTML內容時同時附上純文字版本
+fn build_message(request: &MailJob) -> Result<Message, Vec<String>> {
+    let envelope = &request.envelope;
+    let mut errors = Vec::new();
+    let mut builder = Message::builder().subject(&request.title);
+    if let Some(mailbox) = parse_mailbox("from_address", &envelope.from_address, &mut errors) {
+        builder = builder.from(mailbox);
+    }
+    if let Some(reply_to) = &envelope.reply_to {
+        if let Some(mailbox) = parse_mailbox("reply_to", reply_to, &mut errors) {
+            builder = builder.reply_to(mailbox);
+        }
+    }
+    builder = add_recipients(builder, envelope, &mut errors);
+    let (inline_images, attachments) = parse_parts(envelope, &mut errors);
+    if !inline_images.is_empty() && request.html_content.is_none() {
+        errors.push("inline_images requires html_content.".to_string());
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let message = match (&request.html_content, attachments.is_empty()) {
+        (None, true) => builder.body(request.content.clone()),
+        (None, false) => builder.multipart(attachments.into_iter().fold(
+            MultiPart::mixed().singlepart(SinglePart::plain(request.content.clone())),
+            MultiPart::singlepart,
+        )),
+        (Some(html_content), _) => {
+            let html = SinglePart::html(html_content.clone());
+            let alternative = if inline_images.is_empty() {
+                MultiPart::alternative()
+                    .singlepart(SinglePart::plain(request.content.clone()))
+                    .singlepart(html)
+            } else {
+                MultiPart::alternative()
+                    .singlepart(SinglePart::plain(request.content.clone()))
+                    .multipart(
+                        inline_images
+                            .into_iter()
+                            .fold(MultiPart::related().singlepart(html), MultiPart::singlepart),
+                    )
+            };
+            if attachments.is_empty() {
+                builder.multipart(alternative)
+            } else {
+                builder.multipart(attachments.into_iter().fold(
+                    MultiPart::mixed().multipart(alternative),
+                    MultiPart::singlepart,
+                ))
+            }
+        }
+    };
+    message.map_err(|err| vec![format!("Mail could not be built: {}.", err)])
+}
+
+/// 檢查附件與內嵌圖片的大小總和及MIME類型是否在允許範圍內
+fn check_attachments(envelope: &Envelope, config: &NotificationConfig) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut total_size = 0;
+    for (index, image) in envelope.inline_images.iter().enumerate() {
+        if !image
+            .content_type
+            .to_ascii_lowercase()
+            .starts_with("image/")
+        {
+            errors.push(format!(
+                "inline_images[{}].content_type must be an image type.",
+                index
+            ));
+        }
+        total_size += decoded_len(&image.data);
+    }
+    for (index, attachment) in envelope.attachments.iter().enumerate() {
+        if !attachment_type_allowed(&attachment.content_type, &config.attachment_types) {
+            errors.push(format!(
+                "attachments[{}].content_type {} is not allowed.",
+                index, attachment.content_type
+            ));
+        }
+        total_size += decoded_len(&attachment.data);
+    }
+    if total_size > config.max_attachment_size {
+        errors.push(format!(
+            "attachments and inline_images must not exceed {} bytes in total.",
+            config.max_attachment_size
+        ));
+    }
+    errors
+}
+
+/// 檢查寄件人地址是否在允許清單內（清單空白時不限制，地址格式錯誤由build_message回報）
+fn check_sender(envelope: &Envelope, config: &NotificationConfig) -> Vec<String> {
+    if config.allowed_senders.is_empty() {
+        return Vec::new();
+    }
+    let email = match Mailbox::from_str(&envelope.from_address) {
+        Ok(mailbox) => mailbox.email.to_string().to_ascii_lowercase(),
+        Err(_) => return Vec::new(),
+    };
+    let allowed = config.allowed_senders.iter().any(|sender| {
+        let sender = sender.trim().to_ascii_lowercase();
+        if sender.starts_with('@') {
+            email.ends_with(&sender)
+        } else {
+            email == sender
+        }
+    });
+    if allowed {
+        Vec::new()
+    } else {
+        vec![format!(
+            "from_address {} is not an allowed sender.",
+            envelope.from_address
+        )]
+    }
+}
+
+/// Base64內容解碼後的位元組數
+fn decoded_len(data: &str) -> usize {
+    data.trim_end_matches('=').len() * 3 / 4
+}
+
+/// MIME類型是否符合允許清單（`image/*`表示所有圖片類型）
+fn attachment_type_allowed(content_type: &str, allowed: &[String]) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    allowed.iter().any(|pattern| {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_suffix('*') {
+            Some(prefix) => essence.starts_with(prefix),
+            None => essence == pattern,
+        }
+    })
 }
 
+/// 將郵件存入通知佇列，回傳job ID
 pub async fn sent_mail(
-    Extension(mailer): Extension<SmtpTransport>,
-    CustomJsonRequest(request): CustomJsonRequest<MailRequest>,
+    Authorized(caller, _): Authorized<MailScope>,
+    Extension(outbox): Extension<Arc<Outbox>>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Extension(config): Extension<NotificationConfig>,
+    CustomJsonRequest(Scheduled { schedule, request }): CustomJsonRequest<Scheduled<MailRequest>>,
 ) -> impl IntoResponse {
-    match (
-        (Address::from_str(&request.from_address)),
-        (Address::from_str(&request.to_address)),
+    let send_at = match schedule.resolve() {
+        Ok(send_at) => send_at,
+        Err(error) => return error,
+    };
+    let job = match (
+        request.template,
+        request.title,
+        request.content,
+        request.html_content,
     ) {
-        (Ok(from_address), Ok(to_address)) => {
-            task::spawn(async move {
-                mailer.send(
-                    &Message::builder()
-                        .from(Mailbox::new(None, from_address))
-                        .to(Mailbox::new(None, to_address))
-                        .subject(&request.title)
-                        .body(request.content)
-                        .unwrap(),
-                )
-            });
-            (
-                StatusCode::OK,
-                Json(json!("{'description', 'Mail has been sent.'}")),
+        (Some(template), None, None, None) => match template_service::render(
+            db,
+            &config.default_locale,
+            &template,
+            request.locale.as_deref(),
+            &request.variables,
+        )
+        .await
+        {
+            Ok(rendered) => MailJob {
+                envelope: request.envelope,
+                title: rendered.subject,
+                content: rendered.text,
+                html_content: rendered.html,
+                template: Some(template),
+                locale: Some(rendered.locale),
+                attachment_id: None,
+            },
+            Err(error) => return error,
+        },
+        (None, Some(title), Some(content), html_content) => MailJob {
+            envelope: request.envelope,
+            title,
+            content,
+            html_content,
+            template: None,
+            locale: None,
+            attachment_id: None,
+        },
+        _ => {
+            return err_json_gen(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Some(
+                    "Either template or both title and content (with optional html_content) must be provided."
+                        .to_string(),
+                ),
             )
         }
-        (Ok(_), Err(err)) => err_json_gen(
-            StatusCode::UNPROCESSABLE_ENTITY,
-            Some(format!("to_address could not be parsed: {}.", err)),
-        ),
-        (Err(err), Ok(_)) => err_json_gen(
-            StatusCode::UNPROCESSABLE_ENTITY,
-            Some(format!("from_address could not be parsed: {}.", err)),
-        ),
-        (Err(err1), Err(err2)) => err_json_gen(
+    };
+    let mut errors = check_sender(&job.envelope, &config);
+    errors.extend(check_attachments(&job.envelope, &config));
+    if let Err(reasons) = build_message(&job) {
+        errors.extend(reasons);
+    }
+    if !errors.is_empty() {
+        return err_json_gen(StatusCode::UNPROCESSABLE_ENTITY, Some(errors.join(" ")));
+    }
+    match outbox.enqueue(&caller, Channel::Mail, &job, send_at).await {
+        Ok(job_id) => (StatusCode::ACCEPTED, Json(json!({ "job_id": job_id }))),
+        Err(error) => error,
+    }
+}
+
+/// 批次寄信：範本只查詢一次，逐一套用每位收件人的變數後一併存入通知佇列
+///
+/// 每封郵件都會套用範本並完整驗證，有誤的收件人會被拒絕（列在results，計入rejected），其餘收件人照常寄出；
+/// 寄件人或附件有誤時整個請求回傳422。附件與內嵌圖片只保存一份，各封郵件以attachment_id引用
+pub async fn sent_batch_mail(
+    Authorized(caller, _): Authorized<MailScope>,
+    Extension(outbox): Extension<Arc<Outbox>>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Extension(config): Extension<NotificationConfig>,
+    CustomJsonRequest(Scheduled { schedule, request }): CustomJsonRequest<
+        Scheduled<BatchMailRequest>,
+    >,
+) -> impl IntoResponse {
+    let send_at = match schedule.resolve() {
+        Ok(send_at) => send_at,
+        Err(error) => return error,
+    };
+    if request.recipients.is_empty() || request.recipients.len() > config.batch_limit {
+        return err_json_gen(
             StatusCode::UNPROCESSABLE_ENTITY,
             Some(format!(
-                "from_address could not be parsed: {}. to_address could not be parsed: {}.",
-                err1, err2
+                "recipients must contain 1 to {} entries.",
+                config.batch_limit
             )),
+        );
+    }
+    let mut errors = check_sender(&request.envelope, &config);
+    errors.extend(check_attachments(&request.envelope, &config));
+    if !errors.is_empty() {
+        return err_json_gen(StatusCode::UNPROCESSABLE_ENTITY, Some(errors.join(" ")));
+    }
+    let templates = match TemplateSet::load(db, &request.template).await {
+        Ok(templates) => templates,
+        Err(error) => return get_db_err(error),
+    };
+
+    // 寄件人與附件所有收件人都相同，只需解析一次；各收件人的郵件不帶附件逐一驗證
+    let mut errors = Vec::new();
+    parse_mailbox("from_address", &request.envelope.from_address, &mut errors);
+    if let Some(reply_to) = &request.envelope.reply_to {
+        parse_mailbox("reply_to", reply_to, &mut errors);
+    }
+    parse_parts(&request.envelope, &mut errors);
+    if !errors.is_empty() {
+        return err_json_gen(StatusCode::UNPROCESSABLE_ENTITY, Some(errors.join(" ")));
+    }
+    let has_inline_images = !request.envelope.inline_images.is_empty();
+    let attachment_id =
+        (has_inline_images || !request.envelope.attachments.is_empty()).then(new_uuid_v1);
+
+    // results先記錄被接受的收件人在jobs中的位置，存入佇列後再換成job ID
+    let mut results = Vec::with_capacity(request.recipients.len());
+    let mut jobs = Vec::new();
+    for recipient in request.recipients {
+        let envelope = Envelope {
+            to_address: recipient.to_address,
+            cc_address: recipient.cc_address,
+            bcc_address: recipient.bcc_address,
+            from_address: request.envelope.from_address.clone(),
+            reply_to: request.envelope.reply_to.clone(),
+            inline_images: Vec::new(),
+            attachments: Vec::new(),
+        };
+        let variables = merge_variables(&request.variables, &recipient.variables);
+        let rendered = match templates.render(
+            &config.default_locale,
+            recipient.locale.as_deref(),
+            &variables,
+        ) {
+            Ok(rendered) => rendered,
+            Err(reason) => {
+                results.push(Err(reason));
+                continue;
+            }
+        };
+        if has_inline_images && rendered.html.is_none() {
+            results.push(Err("inline_images requires html_content.".to_string()));
+            continue;
+        }
+        let job = MailJob {
+            envelope,
+            title: rendered.subject,
+            content: rendered.text,
+            html_content: rendered.html,
+            template: Some(request.template.clone()),
+            locale: Some(rendered.locale),
+            attachment_id,
+        };
+        if let Err(reasons) = build_message(&job) {
+            results.push(Err(reasons.join(" ")));
+            continue;
+        }
+        results.push(Ok(jobs.len()));
+        jobs.push(job);
+    }
+
+    if let (Some(attachment_id), false) = (attachment_id, jobs.is_empty()) {
+        if let Err(error) = sqlx::query!(
+            r#"
+            insert into backendmodulesdb.mail_attachment (id, inline_images, attachments)
+            values ($1, $2, $3);
+            "#,
+            attachment_id,
+            json!(request.envelope.inline_images),
+            json!(request.envelope.attachments)
         )
+        .execute(db)
+        .await
+        {
+            return get_db_err(error);
+        }
     }
+    let job_ids = match outbox
+        .enqueue_many(&caller, Channel::Mail, &jobs, send_at)
+        .await
+    {
+        Ok(job_ids) => job_ids,
+        Err(error) => {
+            if let Some(attachment_id) = attachment_id {
+                release_attachments(db, attachment_id).await;
+            }
+            return error;
+        }
+    };
+    batch_response(
+        results
+            .into_iter()
+            .enumerate()
+            .map(|(index, result)| match result {
+                Ok(position) => json!({
+                    "index": index,
+                    "accepted": true,
+                    "job_id": job_ids[position]
+                }),
+                Err(reason) => json!({
+                    "index": index,
+                    "accepted": false,
+                    "reason": reason
+                }),
+            })
+            .collect(),
+    )
+}
+
+/// 讀取批次寄信共用的附件與內嵌圖片（由worker在發送前呼叫）
+pub async fn load_attachments(
+    db: &Pool<Postgres>,
+    mut request: MailJob,
+) -> Result<MailJob, DeliveryError> {
+    let attachment_id = match request.attachment_id {
+        Some(attachment_id) => attachment_id,
+        None => return Ok(request),
+    };
+    let record = sqlx::query!(
+        "select inline_images, attachments from backendmodulesdb.mail_attachment where id = $1;",
+        attachment_id
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|error| DeliveryError {
+        permanent: false,
+        code: None,
+        reason: format!("Unable to load attachments: {}.", error),
+    })?
+    .ok_or_else(|| DeliveryError {
+        permanent: true,
+        code: None,
+        reason: "Attachments of this mail no longer exist.".to_string(),
+    })?;
+    let invalid = |error: serde_json::Error| DeliveryError {
+        permanent: true,
+        code: None,
+        reason: format!("Invalid attachments: {}.", error),
+    };
+    request.envelope.inline_images =
+        serde_json::from_value(record.inline_images).map_err(invalid)?;
+    request.envelope.attachments = serde_json::from_value(record.attachments).map_err(invalid)?;
+    Ok(request)
+}
+
+/// 引用的通知都已結束（沒有等待發送或處理中的通知）時刪除共用的附件
+pub async fn release_attachments(db: &Pool<Postgres>, attachment_id: Uuid) {
+    if let Err(error) = sqlx::query!(
+        r#"
+        delete from backendmodulesdb.mail_attachment
+        where id = $1
+            and not exists (
+                select 1 from backendmodulesdb.notification_job
+                where payload ? 'attachment_id'
+                    and payload ->> 'attachment_id' = $1::text
+                    and state in ('pending', 'processing')
+            );
+        "#,
+        attachment_id
+    )
+    .execute(db)
+    .await
+    {
+        tracing::warn!("Unable to remove mail attachments: {}", error);
+    }
+}
+
+/// 通知payload內引用的共用附件ID
+pub fn attachment_id(payload: &Value) -> Option<Uuid> {
+    payload
+        .get("attachment_id")
+        .and_then(Value::as_str)
+        .and_then(|id| id.parse().ok())
+}
+
+/// 發送郵件（由worker呼叫）
+pub async fn deliver(
+    mailer: &dyn MailTransport,
+    request: MailJob,
+) -> Result<Delivered, DeliveryError> {
+    let message = build_message(&request).map_err(|reasons| DeliveryError {
+        permanent: true,
+        code: None,
+        reason: reasons.join(" "),
+    })?;
+    mailer.send(message).await
+}
+
+/// 查詢memory寄信方式保存的郵件（只在mail.transport為memory時提供，需要notification.read權限）
+pub async fn captured_mail(
+    RequirePermission(_, _): RequirePermission<NotificationRead>,
+    Extension(memory): Extension<Arc<MemoryMailTransport>>,
+) -> impl IntoResponse {
+    Json::from(
+        memory
+            .messages()
+            .into_iter()
+            .map(|mail| {
+                json!({
+                    "from_address": mail.envelope.from().map(ToString::to_string),
+                    "to_address": mail
+                        .envelope
+                        .to()
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<String>>(),
+                    "raw": mail.raw
+                })
+            })
+            .collect::<Vec<Value>>(),
+    )
+}
+
+/// 清除memory寄信方式保存的郵件，回傳清除的數量
+pub async fn clear_captured_mail(
+    RequirePermission(_, _): RequirePermission<NotificationRead>,
+    Extension(memory): Extension<Arc<MemoryMailTransport>>,
+) -> impl IntoResponse {
+    (StatusCode::OK, memory.clear().to_string())
 }
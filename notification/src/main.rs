@@ -1,135 +1,106 @@
 #![forbid(unsafe_code)]
 
-mod apns_service;
-mod mail_service;
-
-use a2::Endpoint;
-use axum::routing::post;
+use axum::routing::{delete, get, post, put};
 use axum::{Extension, Router};
+use base_library::config::Config;
 use base_library::default_fallback;
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::SmtpTransport;
 use mimalloc::MiMalloc;
-use std::env;
-use std::net::SocketAddr;
-use tracing::info;
+use notification::apns_client::ApnsClient;
+use notification::outbox::Outbox;
+use notification::{
+    api_key_service, apns_service, device_service, mail_service, status_service, template_service,
+    topic_service, transport, Dispatcher,
+};
+use sqlx::postgres::PgPoolOptions;
+use std::sync::Arc;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
-#[derive(Clone)]
-pub struct ApnsClientParam {
-    pkcs8_pem_dir: String,
-    key_id: String,
-    team_id: String,
-    endpoint: Endpoint,
-}
-
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3500));
+    let config = Config::init(3500).expect("Configuration failed.");
 
-    let smtp_server = env::var("SMTP_SERVER").ok();
-    let smtp_username = env::var("SMTP_USERNAME").ok();
-    let smtp_password = env::var("SMTP_PASSWORD").ok();
-    let apns_key_dir = env::var("APNS_KEY_DIR").ok();
-    let apns_key_id = env::var("APNS_KEY_ID").ok();
-    let apns_team_id = env::var("APNS_TEAM_ID").ok();
+    let addr = config.bind_address();
 
-    let mailer = match (smtp_username.is_none(), smtp_password.is_none()) {
-        (false, true) => {
-            info!("SMTP username not set, email related function will be disabled.");
-            None
-        }
-        (true, false) => {
-            info!("SMTP password not set, email related function will be disabled.");
-            None
-        }
-        (true, true) => {
-            info!("Both SMTP username and password not set, email related function will be disabled.");
-            None
-        }
-        (false, false) => Some(
-            SmtpTransport::relay(&smtp_server.unwrap_or_else(|| "smtp.google.com".parse().unwrap()))
-                .unwrap()
-                .port(465)
-                .credentials(Credentials::new(
-                    smtp_username.unwrap(),
-                    smtp_password.unwrap(),
-                ))
-                .build(),
-        ),
-    };
+    let db = PgPoolOptions::new()
+        .max_connections(config.database.max_connections)
+        .min_connections(config.database.min_connections)
+        .connect(&config.database.url())
+        .await
+        .expect("Database connection failed.");
 
-    let apns_client_params = match (
-        apns_key_dir.is_none(),
-        apns_key_id.is_none(),
-        apns_team_id.is_none(),
-    ) {
-        (true, false, false) => {
-            info!("APNs key directory not set, APNs related function will be disabled.");
-            None
-        }
-        (true, true, false) => {
-            info!("APNs key directory and KEY_ID not set, APNs related function will be disabled.");
-            None
-        }
-        (true, false, true) => {
-            info!("APNs key directory and TEAM_ID not set, APNs related function will be disabled.");
-            None
-        }
-        (false, true, false) => {
-            info!("APNs KEY_ID not set, APNs related function will be disabled.");
-            None
-        }
-        (false, true, true) => {
-            info!(
-                "APNs KEY_ID and TEAM_ID not set, APNs related function will be disabled."
-            );
-            None
-        }
-        (false, false, true) => {
-            info!("APNs TEAM_ID not set, APNs related function will be disabled.");
-            None
-        }
-        (true, true, true) => {
-            info!("APNs key directory, KEY_ID and TEAM_ID not set, APNs related function will be disabled.");
-            None
-        }
-        (false, false, false) => Some(ApnsClientParam {
-            pkcs8_pem_dir: apns_key_dir.unwrap(),
-            key_id: apns_key_id.unwrap(),
-            team_id: apns_team_id.unwrap(),
-            endpoint: Endpoint::Production,
-        }),
-    };
+    let mailer =
+        transport::from_config(&config.mail).expect("Mail transport configuration failed.");
+
+    let apns_client = ApnsClient::from_config(&config.apns).expect("APNs client creation failed.");
 
-    let mail_router = match mailer {
+    let mail_router = match &mailer {
         None => Router::new(),
-        Some(mailer) => Router::new()
-            .route("/sentMail", post(mail_service::sent_mail))
-            .layer(Extension(mailer)),
+        Some(mailer) => {
+            let router = Router::new()
+                .route("/sentMail", post(mail_service::sent_mail))
+                .route("/sentBatchMail", post(mail_service::sent_batch_mail));
+            match &mailer.memory {
+                None => router,
+                Some(memory) => router
+                    .route(
+                        "/capturedMail",
+                        get(mail_service::captured_mail).delete(mail_service::clear_captured_mail),
+                    )
+                    .layer(Extension(memory.clone())),
+            }
+        }
     };
 
-    let apns_router = match apns_client_params {
+    let apns_router = match apns_client {
         None => Router::new(),
-        Some(apns_client_params) => Router::new()
+        Some(_) => Router::new()
             .route(
                 "/sentAppleNotification",
                 post(apns_service::sent_apple_notification),
             )
-            .layer(Extension(apns_client_params)),
+            .route(
+                "/sentUserNotification",
+                post(apns_service::sent_user_notification),
+            )
+            .route(
+                "/sentBatchUserNotification",
+                post(apns_service::sent_batch_user_notification),
+            )
+            .route(
+                "/sentTopicNotification",
+                post(apns_service::sent_topic_notification),
+            ),
     };
 
-    let app = Router::new().nest(
-        "/notification",
-        Router::new()
-            .merge(mail_router)
-            .merge(apns_router)
-            .fallback(default_fallback),
-    );
+    let outbox = Outbox::new(db.clone(), config.notification.clone());
+    outbox.spawn_workers(Arc::new(Dispatcher {
+        mailer: mailer.map(|mailer| mailer.transport),
+        apns: apns_client,
+    }));
+
+    let app = Router::new()
+        .nest(
+            "/notification",
+            Router::new()
+                .merge(mail_router)
+                .merge(apns_router)
+                .route("/status/:id", get(status_service::status))
+                .route("/list", get(status_service::list))
+                .route("/cancel/:id", delete(status_service::cancel))
+                .route("/reschedule/:id", put(status_service::reschedule))
+                .merge(template_service::router())
+                .merge(device_service::router())
+                .merge(topic_service::router())
+                .merge(api_key_service::router())
+                .fallback(default_fallback),
+        )
+        .layer(Extension(outbox))
+        .layer(Extension(db))
+        .layer(Extension(config.notification.clone()));
 
     println!("Listening on {}", addr);
 
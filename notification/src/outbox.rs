@@ -0,0 +1,448 @@
+use crate::auth::Caller;
+use crate::{apns_service, mail_service, Dispatcher};
+use axum::http::StatusCode;
+use axum::Json;
+use base_library::config::NotificationConfig;
+use base_library::{err_json_gen, get_db_err, new_uuid_v1, now_local_time};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::types::Uuid;
+use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::sync::Notify;
+
+/// 沒有待發送的通知時，worker重新查詢的間隔
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// worker取得通知後的處理時限（秒），超過時視為worker中斷，其他worker可重新取出
+const LEASE_SECONDS: f64 = 300.0;
+
+/// 排程發送最晚可指定的時間（秒）
+const MAX_SCHEDULE_SECONDS: i64 = 366 * 86400;
+
+/// 批次存入通知時每次insert的筆數
+const ENQUEUE_CHUNK_SIZE: usize = 500;
+
+/// 通知管道
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Mail,
+    Apns,
+}
+
+impl Channel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Channel::Mail => "mail",
+            Channel::Apns => "apns",
+        }
+    }
+}
+
+/// 排程發送的時間，send_at（RFC 3339）與delay（秒）擇一，皆未提供時立即發送
+#[derive(Deserialize, Default)]
+pub struct Schedule {
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub send_at: Option<OffsetDateTime>,
+    #[serde(default)]
+    pub delay: Option<u64>,
+}
+
+/// 可排程的通知請求（send_at與delay和請求內容放在同一層）
+#[derive(Deserialize)]
+pub struct Scheduled<T> {
+    #[serde(flatten)]
+    pub schedule: Schedule,
+    #[serde(flatten)]
+    pub request: T,
+}
+
+impl Schedule {
+    /// 換算為發送時間，立即發送時為None
+    pub fn resolve(&self) -> Result<Option<OffsetDateTime>, (StatusCode, Json<Value>)> {
+        let now = now_local_time();
+        let latest = now + time::Duration::seconds(MAX_SCHEDULE_SECONDS);
+        let too_late = || {
+            err_json_gen(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Some(format!(
+                    "Notifications can be scheduled at most {} days ahead.",
+                    MAX_SCHEDULE_SECONDS / 86400
+                )),
+            )
+        };
+        let send_at = match (self.send_at, self.delay) {
+            (Some(_), Some(_)) => {
+                return Err(err_json_gen(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Some("send_at and delay must not be used together.".to_string()),
+                ))
+            }
+            (Some(send_at), None) => send_at,
+            (None, Some(delay)) if delay <= MAX_SCHEDULE_SECONDS as u64 => {
+                now + time::Duration::seconds(delay as i64)
+            }
+            (None, Some(_)) => return Err(too_late()),
+            (None, None) => return Ok(None),
+        };
+        if send_at > latest {
+            return Err(too_late());
+        }
+        Ok(Some(send_at))
+    }
+}
+
+/// 單次發送失敗的原因，permanent為true時不再重試
+#[derive(Debug)]
+pub struct DeliveryError {
+    pub permanent: bool,
+    /// SMTP回應碼或APNs的HTTP狀態碼
+    pub code: Option<String>,
+    pub reason: String,
+}
+
+/// 發送成功時的回應，code為SMTP回應碼或APNs的HTTP狀態碼，detail為SMTP回應內容或apns-id
+pub struct Delivered {
+    pub code: Option<String>,
+    pub detail: Option<String>,
+}
+
+/// 已由worker取出的通知
+struct ClaimedJob {
+    id: Uuid,
+    channel: String,
+    payload: Value,
+    attempts: i32,
+    max_attempts: i32,
+}
+
+/// 通知佇列：API將通知存入資料庫後立即回傳job ID，由worker在背景發送
+pub struct Outbox {
+    db: Pool<Postgres>,
+    config: NotificationConfig,
+    notify: Notify,
+}
+
+impl Outbox {
+    pub fn new(db: Pool<Postgres>, config: NotificationConfig) -> Arc<Outbox> {
+        Arc::new(Outbox {
+            db,
+            config,
+            notify: Notify::new(),
+        })
+    }
+
+    /// 存入通知（記錄呼叫者）並喚醒worker，回傳job ID，send_at為None時立即發送
+    pub async fn enqueue<T: Serialize>(
+        &self,
+        caller: &Caller,
+        channel: Channel,
+        payload: &T,
+        send_at: Option<OffsetDateTime>,
+    ) -> Result<Uuid, (StatusCode, Json<Value>)> {
+        let id = new_uuid_v1();
+        let (api_key, admin) = caller.creator();
+        sqlx::query!(
+            r#"
+            insert into backendmodulesdb.notification_job (
+                id,
+                channel,
+                payload,
+                max_attempts,
+                next_attempt_at,
+                created_by_api_key,
+                created_by_admin
+            )
+            values ($1, $2, $3, $4, coalesce($5, now()), $6, $7);
+            "#,
+            id,
+            channel.as_str(),
+            serde_json::to_value(payload).unwrap(),
+            self.config.max_attempts,
+            send_at,
+            api_key,
+            admin
+        )
+        .execute(&self.db)
+        .await
+        .map_err(get_db_err)?;
+        self.notify.notify_one();
+        Ok(id)
+    }
+
+    /// 批次存入通知（記錄呼叫者）並喚醒worker，依序回傳各筆的job ID（全部存入或全部失敗）
+    pub async fn enqueue_many<T: Serialize>(
+        &self,
+        caller: &Caller,
+        channel: Channel,
+        payloads: &[T],
+        send_at: Option<OffsetDateTime>,
+    ) -> Result<Vec<Uuid>, (StatusCode, Json<Value>)> {
+        let mut ids = Vec::with_capacity(payloads.len());
+        let (api_key, admin) = caller.creator();
+        let mut transaction = self.db.begin().await.map_err(get_db_err)?;
+        for chunk in payloads.chunks(ENQUEUE_CHUNK_SIZE) {
+            let chunk_ids: Vec<Uuid> = chunk.iter().map(|_| new_uuid_v1()).collect();
+            let chunk_payloads: Vec<Value> = chunk
+                .iter()
+                .map(|payload| serde_json::to_value(payload).unwrap())
+                .collect();
+            sqlx::query!(
+                r#"
+                insert into backendmodulesdb.notification_job (
+                    id,
+                    channel,
+                    payload,
+                    max_attempts,
+                    next_attempt_at,
+                    created_by_api_key,
+                    created_by_admin
+                )
+                select id, $3, payload, $4, coalesce($5, now()), $6, $7
+                from unnest($1::uuid[], $2::jsonb[]) as job(id, payload);
+                "#,
+                &chunk_ids,
+                &chunk_payloads,
+                channel.as_str(),
+                self.config.max_attempts,
+                send_at,
+                api_key,
+                admin
+            )
+            .execute(&mut transaction)
+            .await
+            .map_err(get_db_err)?;
+            ids.extend(chunk_ids);
+        }
+        transaction.commit().await.map_err(get_db_err)?;
+        self.notify.notify_waiters();
+        Ok(ids)
+    }
+
+    /// 啟動worker
+    pub fn spawn_workers(self: &Arc<Self>, dispatcher: Arc<Dispatcher>) {
+        for _ in 0..self.config.workers {
+            tokio::spawn(self.clone().work(dispatcher.clone()));
+        }
+    }
+
+    async fn work(self: Arc<Self>, dispatcher: Arc<Dispatcher>) {
+        loop {
+            match self.claim().await {
+                Ok(Some(job)) => self.process(&dispatcher, job).await,
+                Ok(None) => {
+                    let _ = tokio::time::timeout(POLL_INTERVAL, self.notify.notified()).await;
+                }
+                Err(error) => {
+                    tracing::warn!("Unable to fetch notification jobs: {}", error);
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    /// 取出一筆到期的通知（包含處理時限已過的通知），多個worker或多個服務同時取出時不會重複
+    async fn claim(&self) -> Result<Option<ClaimedJob>, sqlx::Error> {
+        sqlx::query_as!(
+            ClaimedJob,
+            r#"
+            update backendmodulesdb.notification_job
+            set state = 'processing',
+                attempts = attempts + 1,
+                locked_until = now() + make_interval(secs => $1),
+                update_timestamp = now()
+            where id = (
+                select id from backendmodulesdb.notification_job
+                where (state = 'pending' and next_attempt_at <= now())
+                    or (state = 'processing' and locked_until < now())
+                order by next_attempt_at
+                limit 1
+                for update skip locked
+            )
+            returning id, channel, payload, attempts, max_attempts;
+            "#,
+            LEASE_SECONDS
+        )
+        .fetch_optional(&self.db)
+        .await
+    }
+
+    async fn process(&self, dispatcher: &Dispatcher, job: ClaimedJob) {
+        let result = if job.attempts > job.max_attempts {
+            // 處理中的worker中斷，而且已經用完嘗試次數
+            Err(DeliveryError {
+                permanent: true,
+                code: None,
+                reason: "Exceeded the maximum number of attempts.".to_string(),
+            })
+        } else {
+            dispatch(dispatcher, &self.db, &job).await
+        };
+
+        let (state, next_attempt_at, last_error, success, code, reason) = match result {
+            Ok(delivered) => ("sent", None, None, true, delivered.code, delivered.detail),
+            Err(error) => {
+                let dead = error.permanent || job.attempts >= job.max_attempts;
+                let last_error = match &error.code {
+                    Some(code) => format!("[{}] {}", code, error.reason),
+                    None => error.reason.clone(),
+                };
+                if dead {
+                    tracing::warn!("Notification {} failed permanently: {}", job.id, last_error);
+                }
+                let next_attempt_at = now_local_time()
+                    + time::Duration::seconds(self.retry_delay(job.attempts) as i64);
+                (
+                    if dead { "dead" } else { "pending" },
+                    Some(next_attempt_at),
+                    Some(last_error),
+                    false,
+                    error.code,
+                    Some(error.reason),
+                )
+            }
+        };
+        if let Err(error) = self
+            .finish(
+                job.id,
+                job.attempts,
+                state,
+                next_attempt_at,
+                last_error,
+                success,
+                code,
+                reason,
+            )
+            .await
+        {
+            tracing::warn!("Unable to update notification {}: {}", job.id, error);
+        } else if state != "pending" && job.channel == "mail" {
+            if let Some(attachment_id) = mail_service::attachment_id(&job.payload) {
+                mail_service::release_attachments(&self.db, attachment_id).await;
+            }
+        }
+    }
+
+    /// 記錄本次發送結果並更新通知狀態
+    #[allow(clippy::too_many_arguments)]
+    async fn finish(
+        &self,
+        id: Uuid,
+        attempt: i32,
+        state: &str,
+        next_attempt_at: Option<OffsetDateTime>,
+        last_error: Option<String>,
+        success: bool,
+        code: Option<String>,
+        reason: Option<String>,
+    ) -> Result<(), sqlx::Error> {
+        let mut transaction = self.db.begin().await?;
+        sqlx::query!(
+            r#"
+            insert into backendmodulesdb.notification_attempt (
+                job_id,
+                attempt,
+                success,
+                code,
+                reason
+            )
+            values ($1, $2, $3, $4, $5);
+            "#,
+            id,
+            attempt,
+            success,
+            code,
+            reason
+        )
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query!(
+            r#"
+            update backendmodulesdb.notification_job
+            set state = $2,
+                next_attempt_at = coalesce($3, next_attempt_at),
+                locked_until = null,
+                last_error = $4,
+                update_timestamp = now()
+            where id = $1;
+            "#,
+            id,
+            state,
+            next_attempt_at,
+            last_error
+        )
+        .execute(&mut transaction)
+        .await?;
+        transaction.commit().await
+    }
+
+    /// 第n次嘗試失敗後的等待秒數（指數退避，有上限）
+    fn retry_delay(&self, attempts: i32) -> u64 {
+        let exponent = attempts.saturating_sub(1).clamp(0, 32) as u32;
+        self.config
+            .retry_base_delay
+            .saturating_mul(2u64.saturating_pow(exponent))
+            .min(self.config.retry_max_delay)
+    }
+}
+
+/// 批次發送的回應：accepted與rejected為筆數，results依請求順序列出每筆的結果（accepted為true或false）
+pub fn batch_response(results: Vec<Value>) -> (StatusCode, Json<Value>) {
+    let accepted = results
+        .iter()
+        .filter(|result| result["accepted"] == true)
+        .count();
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "accepted": accepted,
+            "rejected": results.len() - accepted,
+            "results": results
+        })),
+    )
+}
+
+/// 依通知管道發送
+async fn dispatch(
+    dispatcher: &Dispatcher,
+    db: &Pool<Postgres>,
+    job: &ClaimedJob,
+) -> Result<Delivered, DeliveryError> {
+    let invalid_payload = |error: serde_json::Error| DeliveryError {
+        permanent: true,
+        code: None,
+        reason: format!("Invalid payload: {}.", error),
+    };
+    let disabled = |channel: &str| DeliveryError {
+        permanent: true,
+        code: None,
+        reason: format!("The {} channel is disabled on this server.", channel),
+    };
+    match job.channel.as_str() {
+        "mail" => match &dispatcher.mailer {
+            Some(mailer) => {
+                let request =
+                    serde_json::from_value(job.payload.clone()).map_err(invalid_payload)?;
+                let request = mail_service::load_attachments(db, request).await?;
+                mail_service::deliver(mailer.as_ref(), request).await
+            }
+            None => Err(disabled("mail")),
+        },
+        "apns" => match &dispatcher.apns {
+            Some(apns_client) => {
+                let request =
+                    serde_json::from_value(job.payload.clone()).map_err(invalid_payload)?;
+                apns_service::deliver(apns_client, db, request).await
+            }
+            None => Err(disabled("apns")),
+        },
+        channel => Err(DeliveryError {
+            permanent: true,
+            code: None,
+            reason: format!("Unknown channel {}.", channel),
+        }),
+    }
+}
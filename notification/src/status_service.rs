@@ -0,0 +1,370 @@
+use crate::auth::{AnyScope, Authorized, Caller};
+use crate::mail_service;
+use crate::outbox::Schedule;
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use base_library::permission::{has_permission, NotificationRead, RequirePermission};
+use base_library::{
+    err_json_gen, get_db_err, now_local_time, pagination_offset, CustomJsonRequest,
+    PaginationParams, PaginationResp,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[derive(Serialize)]
+struct NotificationJob {
+    id: Uuid,
+    channel: String,
+    payload: Value,
+    state: String,
+    attempts: i32,
+    max_attempts: i32,
+    #[serde(with = "time::serde::iso8601")]
+    next_attempt_at: OffsetDateTime,
+    last_error: Option<String>,
+    #[serde(with = "time::serde::iso8601")]
+    creation_timestamp: OffsetDateTime,
+    #[serde(with = "time::serde::iso8601")]
+    update_timestamp: OffsetDateTime,
+}
+
+#[derive(Serialize)]
+struct NotificationAttempt {
+    #[serde(skip_serializing)]
+    job_id: Uuid,
+    attempt: i32,
+    success: bool,
+    code: Option<String>,
+    reason: Option<String>,
+    #[serde(with = "time::serde::iso8601")]
+    creation_timestamp: OffsetDateTime,
+}
+
+/// 通知狀態與每次發送的紀錄
+#[derive(Serialize)]
+struct NotificationStatus {
+    #[serde(flatten)]
+    job: NotificationJob,
+    history: Vec<NotificationAttempt>,
+}
+
+/// 通知列表的篩選條件（皆為選填），時間以建立時間篩選，格式為RFC 3339
+#[derive(Deserialize)]
+pub struct StatusFilter {
+    channel: Option<String>,
+    state: Option<String>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    to: Option<OffsetDateTime>,
+}
+
+/// 呼叫者可存取的通知：API Key只能存取自己建立、且管道在scopes內的通知；
+/// 管理員擁有notification.read權限時可存取所有通知，否則只能存取自己建立的通知
+struct JobAccess {
+    all: bool,
+    api_key: Option<Uuid>,
+    admin: Option<Uuid>,
+    channels: Option<Vec<String>>,
+}
+
+impl JobAccess {
+    async fn of(
+        db: &Pool<Postgres>,
+        caller: Caller,
+    ) -> Result<JobAccess, (StatusCode, Json<Value>)> {
+        Ok(match caller {
+            Caller::Admin(claims) => JobAccess {
+                all: has_permission::<NotificationRead>(db, claims.uuid)
+                    .await
+                    .map_err(get_db_err)?,
+                api_key: None,
+                admin: Some(claims.uuid),
+                channels: None,
+            },
+            Caller::ApiKey { id, scopes } => JobAccess {
+                all: false,
+                api_key: Some(id),
+                admin: None,
+                channels: Some(scopes),
+            },
+        })
+    }
+}
+
+/// 查詢通知的發送狀態（無法存取的通知回傳404）
+pub async fn status(
+    Authorized(caller, _): Authorized<AnyScope>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let access = JobAccess::of(db, caller).await?;
+    match sqlx::query_as!(
+        NotificationJob,
+        r#"
+        select id, channel, payload, state, attempts, max_attempts, next_attempt_at, last_error, creation_timestamp, update_timestamp
+        from backendmodulesdb.notification_job
+        where id = $1
+            and ($2 or created_by_api_key = $3 or created_by_admin = $4)
+            and ($5::varchar[] is null or channel = any($5));
+        "#,
+        id,
+        access.all,
+        access.api_key,
+        access.admin,
+        access.channels.as_deref()
+    )
+    .fetch_optional(db)
+    .await
+    {
+        Ok(Some(job)) => match with_history(db, vec![job]).await {
+            Ok(mut result) => Ok(Json::from(json!(result.remove(0)))),
+            Err(error) => Err(get_db_err(error)),
+        },
+        Ok(None) => Err(err_json_gen(
+            StatusCode::NOT_FOUND,
+            Some("Notification not found.".to_string()),
+        )),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 取消尚未發送的通知（等待發送或等待重試），狀態改為cancelled
+pub async fn cancel(
+    Authorized(caller, _): Authorized<AnyScope>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let access = JobAccess::of(db, caller).await?;
+    let result = sqlx::query_as!(
+        NotificationJob,
+        r#"
+        update backendmodulesdb.notification_job
+        set state = 'cancelled',
+            update_timestamp = now()
+        where id = $1 and state = 'pending'
+            and ($2 or created_by_api_key = $3 or created_by_admin = $4)
+            and ($5::varchar[] is null or channel = any($5))
+        returning id, channel, payload, state, attempts, max_attempts, next_attempt_at, last_error, creation_timestamp, update_timestamp;
+        "#,
+        id,
+        access.all,
+        access.api_key,
+        access.admin,
+        access.channels.as_deref()
+    )
+    .fetch_optional(db)
+    .await;
+    if let Ok(Some(job)) = &result {
+        if job.channel == "mail" {
+            if let Some(attachment_id) = mail_service::attachment_id(&job.payload) {
+                mail_service::release_attachments(db, attachment_id).await;
+            }
+        }
+    }
+    pending_job(db, id, &access, result, "cancelled").await
+}
+
+/// 變更尚未發送的通知（等待發送或等待重試）的發送時間
+pub async fn reschedule(
+    Authorized(caller, _): Authorized<AnyScope>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Path(id): Path<Uuid>,
+    CustomJsonRequest(schedule): CustomJsonRequest<Schedule>,
+) -> impl IntoResponse {
+    let send_at = match schedule.resolve() {
+        Ok(Some(send_at)) => send_at,
+        Ok(None) => now_local_time(),
+        Err(error) => return Err(error),
+    };
+    let access = JobAccess::of(db, caller).await?;
+    let result = sqlx::query_as!(
+        NotificationJob,
+        r#"
+        update backendmodulesdb.notification_job
+        set next_attempt_at = $2,
+            update_timestamp = now()
+        where id = $1 and state = 'pending'
+            and ($3 or created_by_api_key = $4 or created_by_admin = $5)
+            and ($6::varchar[] is null or channel = any($6))
+        returning id, channel, payload, state, attempts, max_attempts, next_attempt_at, last_error, creation_timestamp, update_timestamp;
+        "#,
+        id,
+        send_at,
+        access.all,
+        access.api_key,
+        access.admin,
+        access.channels.as_deref()
+    )
+    .fetch_optional(db)
+    .await;
+    pending_job(db, id, &access, result, "rescheduled").await
+}
+
+/// 回傳已更新的通知；沒有更新時，通知不存在或無法存取回傳404，已不是pending狀態回傳409
+async fn pending_job(
+    db: &Pool<Postgres>,
+    id: Uuid,
+    access: &JobAccess,
+    result: Result<Option<NotificationJob>, sqlx::Error>,
+    action: &str,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match result {
+        Ok(Some(job)) => match with_history(db, vec![job]).await {
+            Ok(mut result) => Ok(Json::from(json!(result.remove(0)))),
+            Err(error) => Err(get_db_err(error)),
+        },
+        Ok(None) => match sqlx::query!(
+            r#"
+            select state from backendmodulesdb.notification_job
+            where id = $1
+                and ($2 or created_by_api_key = $3 or created_by_admin = $4)
+                and ($5::varchar[] is null or channel = any($5));
+            "#,
+            id,
+            access.all,
+            access.api_key,
+            access.admin,
+            access.channels.as_deref()
+        )
+        .fetch_optional(db)
+        .await
+        {
+            Ok(Some(record)) => Err(err_json_gen(
+                StatusCode::CONFLICT,
+                Some(format!(
+                    "Only pending notifications can be {}, this notification is {}.",
+                    action, record.state
+                )),
+            )),
+            Ok(None) => Err(err_json_gen(
+                StatusCode::NOT_FOUND,
+                Some("Notification not found.".to_string()),
+            )),
+            Err(error) => Err(get_db_err(error)),
+        },
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 列表每頁最多的筆數
+const MAX_PAGE_SIZE: u64 = 100;
+
+/// 查詢通知列表（分頁查詢，由新到舊排列，無提供參數則使用預設值，每頁最多MAX_PAGE_SIZE筆），列表不包含附件內容
+pub async fn list(
+    RequirePermission(_, _): RequirePermission<NotificationRead>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Query(params): Query<PaginationParams>,
+    Query(filter): Query<StatusFilter>,
+) -> impl IntoResponse {
+    let page = params.page.unwrap_or(1);
+    let count = params.count.unwrap_or(5).min(MAX_PAGE_SIZE);
+    if page == 0 || count == 0 {
+        return Err(err_json_gen(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some("page and count must be greater than 0.".to_string()),
+        ));
+    }
+    let total = match sqlx::query!(
+        r#"
+        select count(*) from backendmodulesdb.notification_job
+        where ($1::varchar is null or channel = $1)
+            and ($2::varchar is null or state = $2)
+            and ($3::timestamptz is null or creation_timestamp >= $3)
+            and ($4::timestamptz is null or creation_timestamp < $4);
+        "#,
+        filter.channel,
+        filter.state,
+        filter.from,
+        filter.to
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(record) => record.count.unwrap_or(0) as u64,
+        Err(error) => return Err(get_db_err(error)),
+    };
+    let offset = pagination_offset(page, count);
+    let mut jobs = match sqlx::query_as!(
+        NotificationJob,
+        r#"
+        select id, channel, payload, state, attempts, max_attempts, next_attempt_at, last_error, creation_timestamp, update_timestamp
+        from backendmodulesdb.notification_job
+        where ($1::varchar is null or channel = $1)
+            and ($2::varchar is null or state = $2)
+            and ($3::timestamptz is null or creation_timestamp >= $3)
+            and ($4::timestamptz is null or creation_timestamp < $4)
+        order by creation_timestamp desc limit $5 offset $6;
+        "#,
+        filter.channel,
+        filter.state,
+        filter.from,
+        filter.to,
+        count as i64,
+        offset as i64
+    )
+    .fetch_all(db)
+    .await
+    {
+        Ok(jobs) => jobs,
+        Err(error) => return Err(get_db_err(error)),
+    };
+    for job in &mut jobs {
+        remove_attachment_data(&mut job.payload);
+    }
+    match with_history(db, jobs).await {
+        Ok(result) => Ok(Json::from(PaginationResp::new(
+            total,
+            count,
+            page,
+            json!(result),
+        ))),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 移除郵件內嵌圖片與附件的Base64內容（保留檔名與類型）
+fn remove_attachment_data(payload: &mut Value) {
+    for key in ["inline_images", "attachments"] {
+        if let Some(Value::Array(parts)) = payload.get_mut(key) {
+            for part in parts.iter_mut().filter_map(Value::as_object_mut) {
+                part.remove("data");
+            }
+        }
+    }
+}
+
+/// 附上每筆通知的發送紀錄
+async fn with_history(
+    db: &Pool<Postgres>,
+    jobs: Vec<NotificationJob>,
+) -> Result<Vec<NotificationStatus>, sqlx::Error> {
+    let ids = jobs.iter().map(|job| job.id).collect::<Vec<Uuid>>();
+    let mut history = HashMap::<Uuid, Vec<NotificationAttempt>>::new();
+    for attempt in sqlx::query_as!(
+        NotificationAttempt,
+        r#"
+        select job_id, attempt, success, code, reason, creation_timestamp
+        from backendmodulesdb.notification_attempt where job_id = any($1) order by id;
+        "#,
+        &ids
+    )
+    .fetch_all(db)
+    .await?
+    {
+        history.entry(attempt.job_id).or_default().push(attempt);
+    }
+    Ok(jobs
+        .into_iter()
+        .map(|job| NotificationStatus {
+            history: history.remove(&job.id).unwrap_or_default(),
+            job,
+        })
+        .collect())
+}
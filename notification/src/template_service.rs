@@ -0,0 +1,403 @@
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::{delete, get, post, put};
+use axum::{Extension, Json, Router};
+use base_library::config::NotificationConfig;
+use base_library::permission::{RequirePermission, TemplateDelete, TemplateRead, TemplateWrite};
+use base_library::{
+    default_fallback, err_json_gen, get_db_err, now_local_time, pagination_offset,
+    CustomJsonRequest, PaginationParams, PaginationResp,
+};
+use minijinja::Environment;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{Pool, Postgres};
+use time::OffsetDateTime;
+
+pub fn router() -> Router {
+    Router::new().nest(
+        "/template",
+        Router::new()
+            .route("/list", get(list))
+            .route("/query/:id", get(query))
+            .route("/save", put(save))
+            .route("/delete/:id", delete(remove))
+            .route("/preview", post(preview))
+            .fallback(default_fallback),
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, sqlx::FromRow)]
+struct MailTemplate {
+    id: i32,
+    template_name: String,
+    locale: String,
+    subject: String,
+    text_body: String,
+    html_body: Option<String>,
+    note: Option<String>,
+    #[serde(with = "time::serde::iso8601")]
+    creation_timestamp: OffsetDateTime,
+    #[serde(with = "time::serde::iso8601")]
+    update_timestamp: OffsetDateTime,
+}
+
+#[derive(Deserialize)]
+struct MailTemplateReq {
+    id: Option<i32>,
+    template_name: String,
+    locale: String,
+    subject: String,
+    text_body: String,
+    html_body: Option<String>,
+    note: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PreviewReq {
+    template: String,
+    locale: Option<String>,
+    #[serde(default)]
+    variables: Value,
+}
+
+/// 範本套用變數後的結果，locale為實際使用的語系
+#[derive(Serialize)]
+pub struct Rendered {
+    pub locale: String,
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+}
+
+/// 查詢範本列表（分頁查詢，無提供參數則使用預設值）
+async fn list(
+    RequirePermission(_, _): RequirePermission<TemplateRead>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Query(params): Query<PaginationParams>,
+) -> impl IntoResponse {
+    let page = params.page.unwrap_or(1);
+    let count = params.count.unwrap_or(5);
+    let total = match sqlx::query!("select count(*) from backendmodulesdb.mail_template;")
+        .fetch_one(db)
+        .await
+    {
+        Ok(record) => record.count.unwrap_or(0) as u64,
+        Err(error) => return Err(get_db_err(error)),
+    };
+    let offset = pagination_offset(page, count);
+    match sqlx::query_as!(
+        MailTemplate,
+        "select * from backendmodulesdb.mail_template order by template_name, locale limit $1 offset $2;",
+        count as i64,
+        offset as i64
+    )
+    .fetch_all(db)
+    .await
+    {
+        Ok(result) => Ok(Json::from(PaginationResp::new(
+            total,
+            count,
+            page,
+            json!(result),
+        ))),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 查詢範本
+async fn query(
+    RequirePermission(_, _): RequirePermission<TemplateRead>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    match sqlx::query_as!(
+        MailTemplate,
+        "select * from backendmodulesdb.mail_template where id = $1",
+        id
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(template) => Ok(Json::from(json!(template))),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 儲存範本（有提供ID的情況更新，無則新增），範本語法錯誤時拒絕
+async fn save(
+    RequirePermission(_, _): RequirePermission<TemplateWrite>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(params): CustomJsonRequest<MailTemplateReq>,
+) -> impl IntoResponse {
+    if let Err(reason) = compile(
+        &params.subject,
+        &params.text_body,
+        params.html_body.as_deref(),
+    ) {
+        return Err(err_json_gen(StatusCode::UNPROCESSABLE_ENTITY, Some(reason)));
+    }
+    let query = match params.id {
+        None => {
+            sqlx::query_as!(
+                MailTemplate,
+                r#"
+            insert into backendmodulesdb.mail_template (
+                template_name,
+                locale,
+                subject,
+                text_body,
+                html_body,
+                note,
+                creation_timestamp,
+                update_timestamp
+            )
+            values (
+                $1,
+                $2,
+                $3,
+                $4,
+                $5,
+                $6,
+                $7,
+                $7
+            ) returning *;
+            "#,
+                params.template_name,
+                params.locale,
+                params.subject,
+                params.text_body,
+                params.html_body,
+                params.note,
+                now_local_time()
+            )
+            .fetch_one(db)
+            .await
+        }
+        Some(id) => {
+            sqlx::query_as!(
+                MailTemplate,
+                r#"
+            update backendmodulesdb.mail_template
+            set template_name = $2,
+                locale = $3,
+                subject = $4,
+                text_body = $5,
+                html_body = $6,
+                note = $7,
+                update_timestamp = $8
+            where id = $1 returning *;
+            "#,
+                id,
+                params.template_name,
+                params.locale,
+                params.subject,
+                params.text_body,
+                params.html_body,
+                params.note,
+                now_local_time()
+            )
+            .fetch_one(db)
+            .await
+        }
+    };
+    match query {
+        Ok(result) => Ok(Json::from(json!(result))),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 移除範本
+async fn remove(
+    RequirePermission(_, _): RequirePermission<TemplateDelete>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    match sqlx::query_as!(
+        MailTemplate,
+        "delete from backendmodulesdb.mail_template where id = $1 returning *;",
+        id
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(template) => Ok((StatusCode::OK, template.id.to_string())),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 預覽範本套用變數後的結果（不會寄出）
+async fn preview(
+    RequirePermission(_, _): RequirePermission<TemplateRead>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Extension(config): Extension<NotificationConfig>,
+    CustomJsonRequest(params): CustomJsonRequest<PreviewReq>,
+) -> impl IntoResponse {
+    match render(
+        db,
+        &config.default_locale,
+        &params.template,
+        params.locale.as_deref(),
+        &params.variables,
+    )
+    .await
+    {
+        Ok(rendered) => Ok(Json::from(json!(rendered))),
+        Err(error) => Err(error),
+    }
+}
+
+/// 依語系取得範本並套用變數，找不到指定語系時依序改用語言代碼（zh-TW → zh）與預設語系
+pub async fn render(
+    db: &Pool<Postgres>,
+    default_locale: &str,
+    template_name: &str,
+    locale: Option<&str>,
+    variables: &Value,
+) -> Result<Rendered, (StatusCode, Json<Value>)> {
+    TemplateSet::load(db, template_name)
+        .await
+        .map_err(get_db_err)?
+        .render(default_locale, locale, variables)
+        .map_err(|reason| err_json_gen(StatusCode::UNPROCESSABLE_ENTITY, Some(reason)))
+}
+
+/// 同一個範本名稱的所有語系（批次寄信時只查詢一次）
+pub struct TemplateSet {
+    template_name: String,
+    templates: Vec<MailTemplate>,
+}
+
+impl TemplateSet {
+    pub async fn load(
+        db: &Pool<Postgres>,
+        template_name: &str,
+    ) -> Result<TemplateSet, sqlx::Error> {
+        let templates = sqlx::query_as!(
+            MailTemplate,
+            "select * from backendmodulesdb.mail_template where template_name = $1",
+            template_name
+        )
+        .fetch_all(db)
+        .await?;
+        Ok(TemplateSet {
+            template_name: template_name.to_string(),
+            templates,
+        })
+    }
+
+    /// 依語系選擇範本並套用變數，找不到指定語系時依序改用語言代碼（zh-TW → zh）與預設語系
+    pub fn render(
+        &self,
+        default_locale: &str,
+        locale: Option<&str>,
+        variables: &Value,
+    ) -> Result<Rendered, String> {
+        let template = locale_candidates(locale, default_locale)
+            .iter()
+            .find_map(|candidate| {
+                self.templates
+                    .iter()
+                    .find(|template| template.locale.eq_ignore_ascii_case(candidate))
+            })
+            .ok_or_else(|| {
+                format!(
+                    "Template {} has no {} or {} variant.",
+                    self.template_name,
+                    locale.unwrap_or(default_locale),
+                    default_locale
+                )
+            })?;
+        // 副檔名為.html的範本會自動跳脫變數中的HTML字元
+        let mut environment = Environment::new();
+        let parts = [
+            ("subject.txt", Some(template.subject.as_str())),
+            ("text.txt", Some(template.text_body.as_str())),
+            ("html.html", template.html_body.as_deref()),
+        ];
+        for (name, source) in parts {
+            if let Some(source) = source {
+                environment
+                    .add_template(name, source)
+                    .map_err(|error| template_error(name, error))?;
+            }
+        }
+        let render = |name: &str| {
+            environment
+                .get_template(name)
+                .and_then(|template| template.render(variables))
+                .map_err(|error| template_error(name, error))
+        };
+        Ok(Rendered {
+            locale: template.locale.clone(),
+            subject: render("subject.txt")?.trim().to_string(),
+            text: render("text.txt")?,
+            html: match &template.html_body {
+                Some(_) => Some(render("html.html")?),
+                None => None,
+            },
+        })
+    }
+}
+
+/// 將純文字套用變數（批次推播的通知內容）
+pub fn render_text(name: &str, source: &str, variables: &Value) -> Result<String, String> {
+    let mut environment = Environment::new();
+    environment
+        .add_template(name, source)
+        .and_then(|_| environment.get_template(name))
+        .and_then(|template| template.render(variables))
+        .map_err(|error| template_error(name, error))
+}
+
+/// 合併共用變數與收件人的變數（同名時以收件人的值為準）
+pub fn merge_variables(shared: &Value, own: &Value) -> Value {
+    match (shared, own) {
+        (Value::Object(shared), Value::Object(own)) => {
+            let mut merged = shared.clone();
+            merged.extend(own.clone());
+            Value::Object(merged)
+        }
+        (shared, Value::Null) => shared.clone(),
+        (_, own) => own.clone(),
+    }
+}
+
+/// 檢查範本語法
+fn compile(subject: &str, text_body: &str, html_body: Option<&str>) -> Result<(), String> {
+    let mut environment = Environment::new();
+    let parts = [
+        ("subject", Some(subject)),
+        ("text_body", Some(text_body)),
+        ("html_body", html_body),
+    ];
+    for (name, source) in parts {
+        if let Some(source) = source {
+            environment
+                .add_template(name, source)
+                .map_err(|error| format!("{} could not be parsed: {}.", name, error))?;
+        }
+    }
+    Ok(())
+}
+
+/// 語系的候選順序：指定語系、語言代碼、預設語系
+fn locale_candidates(locale: Option<&str>, default_locale: &str) -> Vec<String> {
+    let mut candidates = Vec::new();
+    if let Some(locale) = locale {
+        let locale = locale.replace('_', "-");
+        if let Some((language, _)) = locale.split_once('-') {
+            candidates.push(locale.clone());
+            candidates.push(language.to_string());
+        } else {
+            candidates.push(locale);
+        }
+    }
+    candidates.push(default_locale.to_string());
+    candidates
+}
+
+fn template_error(name: &str, error: minijinja::Error) -> String {
+    format!("Template {} could not be rendered: {}.", name, error)
+}
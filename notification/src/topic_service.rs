@@ -0,0 +1,194 @@
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::{delete, get, put};
+use axum::{Extension, Json, Router};
+use base_library::permission::{RequirePermission, TopicWrite};
+use base_library::{
+    default_fallback, err_json_gen, get_db_err, now_local_time, CustomJsonRequest, UserToken,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use time::OffsetDateTime;
+
+pub fn router() -> Router {
+    Router::new().nest(
+        "/topic",
+        Router::new()
+            .route("/list", get(list))
+            .route("/save", put(save))
+            .route("/delete/:topic_name", delete(remove))
+            .route("/subscriptions", get(subscriptions))
+            .route("/subscribe/:topic_name", put(subscribe))
+            .route("/unsubscribe/:topic_name", delete(unsubscribe))
+            .fallback(default_fallback),
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, sqlx::FromRow)]
+struct Topic {
+    topic_name: String,
+    note: Option<String>,
+    #[serde(with = "time::serde::iso8601")]
+    creation_timestamp: OffsetDateTime,
+    #[serde(with = "time::serde::iso8601")]
+    update_timestamp: OffsetDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, sqlx::FromRow)]
+struct Subscription {
+    topic_name: String,
+    #[serde(with = "time::serde::iso8601")]
+    creation_timestamp: OffsetDateTime,
+}
+
+#[derive(Deserialize)]
+struct TopicReq {
+    topic_name: String,
+    note: Option<String>,
+}
+
+/// 查詢所有主題（用戶可訂閱的主題）
+async fn list(
+    UserToken(_): UserToken,
+    Extension(ref db): Extension<Pool<Postgres>>,
+) -> impl IntoResponse {
+    match sqlx::query_as!(
+        Topic,
+        "select * from backendmodulesdb.notification_topic order by topic_name;"
+    )
+    .fetch_all(db)
+    .await
+    {
+        Ok(result) => Ok(Json::from(json!(result))),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 儲存主題（主題已存在時更新說明）
+async fn save(
+    RequirePermission(_, _): RequirePermission<TopicWrite>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(params): CustomJsonRequest<TopicReq>,
+) -> impl IntoResponse {
+    if params.topic_name.trim().is_empty() || params.topic_name.len() > 64 {
+        return Err(err_json_gen(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some("topic_name must contain 1 to 64 bytes.".to_string()),
+        ));
+    }
+    match sqlx::query_as!(
+        Topic,
+        r#"
+        insert into backendmodulesdb.notification_topic (
+            topic_name,
+            note,
+            creation_timestamp,
+            update_timestamp
+        )
+        values (
+            $1,
+            $2,
+            $3,
+            $3
+        )
+        on conflict (topic_name) do update
+        set note = excluded.note,
+            update_timestamp = excluded.update_timestamp
+        returning *;
+        "#,
+        params.topic_name,
+        params.note,
+        now_local_time()
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(result) => Ok(Json::from(json!(result))),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 移除主題（一併移除所有訂閱）
+async fn remove(
+    RequirePermission(_, _): RequirePermission<TopicWrite>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Path(topic_name): Path<String>,
+) -> impl IntoResponse {
+    match sqlx::query_as!(
+        Topic,
+        "delete from backendmodulesdb.notification_topic where topic_name = $1 returning *;",
+        topic_name
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(topic) => Ok((StatusCode::OK, topic.topic_name)),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 查詢目前用戶訂閱的主題
+async fn subscriptions(
+    UserToken(claims): UserToken,
+    Extension(ref db): Extension<Pool<Postgres>>,
+) -> impl IntoResponse {
+    match sqlx::query_as!(
+        Subscription,
+        "select topic_name, creation_timestamp from backendmodulesdb.topic_subscription where user_uuid = $1 order by topic_name;",
+        claims.uuid
+    )
+    .fetch_all(db)
+    .await
+    {
+        Ok(result) => Ok(Json::from(json!(result))),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 目前用戶訂閱主題（已訂閱時不變）
+async fn subscribe(
+    UserToken(claims): UserToken,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Path(topic_name): Path<String>,
+) -> impl IntoResponse {
+    match sqlx::query_as!(
+        Subscription,
+        r#"
+        insert into backendmodulesdb.topic_subscription (topic_name, user_uuid)
+        select topic_name, $2 from backendmodulesdb.notification_topic where topic_name = $1
+        on conflict (topic_name, user_uuid) do update
+        set topic_name = excluded.topic_name
+        returning topic_name, creation_timestamp;
+        "#,
+        topic_name,
+        claims.uuid
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(result) => Ok(Json::from(json!(result))),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+/// 目前用戶取消訂閱主題
+async fn unsubscribe(
+    UserToken(claims): UserToken,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Path(topic_name): Path<String>,
+) -> impl IntoResponse {
+    match sqlx::query_as!(
+        Subscription,
+        "delete from backendmodulesdb.topic_subscription where topic_name = $1 and user_uuid = $2 returning topic_name, creation_timestamp;",
+        topic_name,
+        claims.uuid
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(subscription) => Ok((StatusCode::OK, subscription.topic_name)),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
@@ -0,0 +1,205 @@
+use crate::outbox::{Delivered, DeliveryError};
+use axum::async_trait;
+use base_library::config::{MailConfig, MailTransportKind, SmtpTls};
+use lettre::address::Envelope;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{
+    AsyncFileTransport, AsyncSendmailTransport, AsyncSmtpTransport, AsyncTransport, Message,
+    Tokio1Executor,
+};
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+/// 寄信方式，依設定的mail.transport選擇實作
+#[async_trait]
+pub trait MailTransport: Send + Sync {
+    async fn send(&self, message: Message) -> Result<Delivered, DeliveryError>;
+}
+
+/// 依設定建立的寄信方式，memory另外保留MemoryMailTransport以便讀取保存的郵件
+pub struct ConfiguredMailer {
+    pub transport: Arc<dyn MailTransport>,
+    pub memory: Option<Arc<MemoryMailTransport>>,
+}
+
+impl ConfiguredMailer {
+    fn new(transport: impl MailTransport + 'static) -> ConfiguredMailer {
+        ConfiguredMailer {
+            transport: Arc::new(transport),
+            memory: None,
+        }
+    }
+}
+
+/// 依設定建立寄信方式，SMTP缺少帳號或密碼時回傳None（停用寄信功能）
+pub fn from_config(config: &MailConfig) -> Result<Option<ConfiguredMailer>, String> {
+    match config.transport {
+        MailTransportKind::Smtp => SmtpMailTransport::from_config(config)
+            .map(|transport| transport.map(ConfiguredMailer::new)),
+        MailTransportKind::File => {
+            std::fs::create_dir_all(&config.file_dir).map_err(|error| {
+                format!(
+                    "Unable to create mail directory {}: {}",
+                    config.file_dir, error
+                )
+            })?;
+            Ok(Some(ConfiguredMailer::new(FileMailTransport(
+                AsyncFileTransport::new(&config.file_dir),
+            ))))
+        }
+        MailTransportKind::Sendmail => Ok(Some(ConfiguredMailer::new(SendmailMailTransport(
+            match &config.sendmail_command {
+                Some(command) => AsyncSendmailTransport::new_with_command(command),
+                None => AsyncSendmailTransport::new(),
+            },
+        )))),
+        MailTransportKind::Memory => {
+            let memory = Arc::new(MemoryMailTransport::default());
+            Ok(Some(ConfiguredMailer {
+                transport: memory.clone(),
+                memory: Some(memory),
+            }))
+        }
+    }
+}
+
+/// 透過SMTP伺服器寄出，5xx回應視為永久失敗
+pub struct SmtpMailTransport(AsyncSmtpTransport<Tokio1Executor>);
+
+impl SmtpMailTransport {
+    fn from_config(config: &MailConfig) -> Result<Option<SmtpMailTransport>, String> {
+        let credentials = match (config.username.clone(), config.password.clone()) {
+            (Some(username), Some(password)) => Some(Credentials::new(username, password)),
+            (Some(_), None) => {
+                info!("SMTP password not set, email related function will be disabled.");
+                return Ok(None);
+            }
+            (None, Some(_)) => {
+                info!("SMTP username not set, email related function will be disabled.");
+                return Ok(None);
+            }
+            (None, None) if config.tls != SmtpTls::None => {
+                info!("Both SMTP username and password not set, email related function will be disabled.");
+                return Ok(None);
+            }
+            (None, None) => None,
+        };
+        let builder = match config.tls {
+            SmtpTls::Implicit => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.server),
+            SmtpTls::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.server)
+            }
+            SmtpTls::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                &config.server,
+            )),
+        }
+        .map_err(|error| format!("Invalid SMTP server {}: {}", config.server, error))?
+        .port(config.port);
+        let builder = match credentials {
+            Some(credentials) => builder.credentials(credentials),
+            None => builder,
+        };
+        Ok(Some(SmtpMailTransport(builder.build())))
+    }
+}
+
+#[async_trait]
+impl MailTransport for SmtpMailTransport {
+    async fn send(&self, message: Message) -> Result<Delivered, DeliveryError> {
+        match self.0.send(message).await {
+            Ok(response) => Ok(Delivered {
+                code: Some(response.code().to_string()),
+                detail: Some(response.message().collect::<Vec<&str>>().join(" ")),
+            }),
+            Err(error) => Err(DeliveryError {
+                permanent: error.is_permanent(),
+                code: error.status().map(|code| code.to_string()),
+                reason: error.to_string(),
+            }),
+        }
+    }
+}
+
+/// 寫入.eml檔案，不會寄出
+pub struct FileMailTransport(AsyncFileTransport<Tokio1Executor>);
+
+#[async_trait]
+impl MailTransport for FileMailTransport {
+    async fn send(&self, message: Message) -> Result<Delivered, DeliveryError> {
+        match self.0.send(message).await {
+            Ok(id) => Ok(Delivered {
+                code: None,
+                detail: Some(format!("Written to {}.eml", id)),
+            }),
+            Err(error) => Err(DeliveryError {
+                permanent: false,
+                code: None,
+                reason: error.to_string(),
+            }),
+        }
+    }
+}
+
+/// 呼叫本機的sendmail寄出
+pub struct SendmailMailTransport(AsyncSendmailTransport<Tokio1Executor>);
+
+#[async_trait]
+impl MailTransport for SendmailMailTransport {
+    async fn send(&self, message: Message) -> Result<Delivered, DeliveryError> {
+        match self.0.send(message).await {
+            Ok(()) => Ok(Delivered {
+                code: None,
+                detail: None,
+            }),
+            Err(error) => Err(DeliveryError {
+                permanent: false,
+                code: None,
+                reason: error.to_string(),
+            }),
+        }
+    }
+}
+
+/// 保存在記憶體的郵件
+#[derive(Clone, Debug)]
+pub struct CapturedMail {
+    pub envelope: Envelope,
+    /// 完整的郵件內容（RFC 5322格式）
+    pub raw: String,
+}
+
+/// 將郵件保存在記憶體，不會寄出（本機與整合測試用）
+#[derive(Default)]
+pub struct MemoryMailTransport {
+    messages: Mutex<Vec<CapturedMail>>,
+}
+
+impl MemoryMailTransport {
+    /// 目前保存的所有郵件
+    pub fn messages(&self) -> Vec<CapturedMail> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    /// 清除保存的郵件，回傳清除的數量
+    pub fn clear(&self) -> usize {
+        let mut messages = self.messages.lock().unwrap();
+        let count = messages.len();
+        messages.clear();
+        count
+    }
+}
+
+#[async_trait]
+impl MailTransport for MemoryMailTransport {
+    async fn send(&self, message: Message) -> Result<Delivered, DeliveryError> {
+        let captured = CapturedMail {
+            envelope: message.envelope().clone(),
+            raw: String::from_utf8_lossy(&message.formatted()).to_string(),
+        };
+        self.messages.lock().unwrap().push(captured);
+        Ok(Delivered {
+            code: None,
+            detail: None,
+        })
+    }
+}
@@ -0,0 +1,24 @@
+use notification::template_service::merge_variables;
+use serde_json::json;
+
+#[test]
+fn recipient_variables_override_shared_variables() {
+    let merged = merge_variables(
+        &json!({"product": "Backend", "name": "Customer"}),
+        &json!({"name": "Ming", "order": 42}),
+    );
+    assert_eq!(
+        merged,
+        json!({"product": "Backend", "name": "Ming", "order": 42})
+    );
+}
+
+#[test]
+fn missing_recipient_variables_keep_shared_variables() {
+    let shared = json!({"product": "Backend"});
+    assert_eq!(merge_variables(&shared, &json!(null)), shared);
+    assert_eq!(
+        merge_variables(&json!(null), &json!({"name": "Ming"})),
+        json!({"name": "Ming"})
+    );
+}
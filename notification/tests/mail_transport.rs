@@ -0,0 +1,113 @@
+use base_library::config::{MailConfig, MailTransportKind};
+use notification::mail_service::{deliver, MailJob};
+use notification::transport::{from_config, MemoryMailTransport};
+use serde_json::json;
+
+fn job(value: serde_json::Value) -> MailJob {
+    serde_json::from_value(value).unwrap()
+}
+
+#[tokio::test]
+async fn memory_transport_captures_multipart_mail() {
+    let transport = MemoryMailTransport::default();
+    deliver(
+        &transport,
+        job(json!({
+            "from_address": "Service <service@example.com>",
+            "to_address": ["Ming <ming@example.com>", "hua@example.com"],
+            "cc_address": "lead@example.com",
+            "bcc_address": ["audit@example.com"],
+            "reply_to": "support@example.com",
+            "title": "Report",
+            "content": "See attached.",
+            "html_content": "<p>See attached.</p><img src=\"cid:logo\">",
+            "inline_images": [
+                {"content_id": "logo", "content_type": "image/png", "data": "iVBORw0KGgo="}
+            ],
+            "attachments": [
+                {"filename": "report.csv", "content_type": "text/csv", "data": "YSxiCjEsMgo="}
+            ]
+        })),
+    )
+    .await
+    .unwrap();
+
+    let messages = transport.messages();
+    assert_eq!(messages.len(), 1);
+    let mail = &messages[0];
+    let recipients: Vec<String> = mail
+        .envelope
+        .to()
+        .iter()
+        .map(|address| address.to_string())
+        .collect();
+    assert_eq!(
+        recipients,
+        [
+            "ming@example.com",
+            "hua@example.com",
+            "lead@example.com",
+            "audit@example.com"
+        ]
+    );
+    assert!(mail.raw.contains("Reply-To: support@example.com"));
+    assert!(!mail.raw.contains("Bcc:"));
+    assert!(mail.raw.contains("multipart/mixed"));
+    assert!(mail.raw.contains("multipart/alternative"));
+    assert!(mail.raw.contains("multipart/related"));
+    assert!(mail.raw.contains("Content-ID: <logo>"));
+    assert!(mail.raw.contains("report.csv"));
+
+    transport.clear();
+    assert!(transport.messages().is_empty());
+}
+
+#[tokio::test]
+async fn configured_memory_transport_exposes_captured_mail() {
+    let config = MailConfig {
+        transport: MailTransportKind::Memory,
+        ..MailConfig::default()
+    };
+    let mailer = from_config(&config).unwrap().unwrap();
+    let memory = mailer.memory.expect("memory transport handle");
+    deliver(
+        mailer.transport.as_ref(),
+        job(json!({
+            "from_address": "service@example.com",
+            "to_address": "ming@example.com",
+            "title": "Hello",
+            "content": "Hello."
+        })),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(memory.messages().len(), 1);
+    assert_eq!(memory.clear(), 1);
+    assert!(memory.messages().is_empty());
+}
+
+#[tokio::test]
+async fn invalid_fields_are_reported_by_name() {
+    let transport = MemoryMailTransport::default();
+    let error = deliver(
+        &transport,
+        job(json!({
+            "from_address": "service@example.com",
+            "to_address": ["ming@example.com", "not an address"],
+            "title": "Report",
+            "content": "See attached.",
+            "attachments": [
+                {"filename": "report.csv", "content_type": "text/csv", "data": "not base64!"}
+            ]
+        })),
+    )
+    .await
+    .err()
+    .unwrap();
+
+    assert!(error.permanent);
+    assert!(error.reason.contains("to_address[1]"));
+    assert!(error.reason.contains("attachments[0].data"));
+    assert!(transport.messages().is_empty());
+}
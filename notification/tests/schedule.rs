@@ -0,0 +1,41 @@
+use base_library::now_local_time;
+use notification::outbox::Schedule;
+use time::Duration;
+
+#[test]
+fn delay_is_converted_to_send_time() {
+    let before = now_local_time();
+    let send_at = Schedule {
+        send_at: None,
+        delay: Some(600),
+    }
+    .resolve()
+    .unwrap()
+    .unwrap();
+    assert!(send_at >= before + Duration::seconds(600));
+    assert!(send_at <= now_local_time() + Duration::seconds(600));
+}
+
+#[test]
+fn missing_schedule_sends_immediately() {
+    assert!(Schedule::default().resolve().unwrap().is_none());
+}
+
+#[test]
+fn invalid_schedules_are_rejected() {
+    let both = Schedule {
+        send_at: Some(now_local_time()),
+        delay: Some(60),
+    };
+    let too_late = Schedule {
+        send_at: Some(now_local_time() + Duration::days(400)),
+        delay: None,
+    };
+    let huge_delay = Schedule {
+        send_at: None,
+        delay: Some(u64::MAX),
+    };
+    for schedule in [both, too_late, huge_delay] {
+        assert!(schedule.resolve().is_err());
+    }
+}
@@ -1,14 +1,19 @@
 use axum::extract::{Path, Query};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::routing::{delete, get, put};
+use axum::routing::{delete, get, patch, put};
 use axum::{Extension, Json, Router};
+use base_library::list_query::{AccountFilter, ListParams, ListQuery, ListSpec};
+use base_library::password::{check_password_policy, hash_password};
+use base_library::patch::{patch_account, AccountPatchSpec};
+use base_library::permission::{RequirePermission, UserDelete, UserRead, UserWrite};
+use base_library::token::{revoke_account_sessions, TokenOwner};
 use base_library::{
-    default_fallback, err_json_gen, get_db_err, new_uuid_v1, now_local_time, pagination_offset,
-    AdminToken, CustomJsonRequest, PaginationParams, PaginationResp,
+    default_fallback, err_json_gen, get_db_err, new_uuid_v1, now_local_time, CustomJsonRequest,
+    PaginationParams,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use sqlx::{Pool, Postgres};
 use time::OffsetDateTime;
 use uuid::Uuid;
@@ -20,6 +25,7 @@ pub fn router() -> Router {
             .route("/list", get(list))
             .route("/query/:uuid", get(query))
             .route("/save", put(save))
+            .route("/patch/:uuid", patch(partial_update))
             .route("/delete/:uuid", delete(remove))
             .fallback(default_fallback),
     )
@@ -29,12 +35,17 @@ pub fn router() -> Router {
 struct UserInfo {
     uuid: Uuid,
     login_account: String,
+    /// 新增時必填；更新時留空代表不變更。不會出現在回應內
+    #[serde(default, skip_serializing)]
     login_password: String,
     account_rule: i32,
     account_status: bool,
     user_name: String,
     user_email: String,
     note: Option<String>,
+    /// 管理員新增的帳號視為已驗證
+    #[serde(skip_deserializing)]
+    email_verified: bool,
     #[serde(
         skip_deserializing,
         default = "now_local_time",
@@ -50,7 +61,7 @@ struct UserInfo {
 }
 
 async fn query(
-    AdminToken(_): AdminToken,
+    RequirePermission(_, _): RequirePermission<UserRead>,
     Extension(ref db): Extension<Pool<Postgres>>,
     Path(search_uuid): Path<Uuid>,
 ) -> impl IntoResponse {
@@ -67,47 +78,65 @@ async fn query(
     }
 }
 
-/// 查詢管理員列表（分頁查詢，無提供參數則使用預設值）
+/// 用戶列表可搜尋與排序的欄位
+const USER_LIST: ListSpec = ListSpec {
+    table: "backendmodulesdb.user_info",
+    primary_key: "uuid",
+    search_columns: &["login_account", "user_name", "user_email"],
+    sort_columns: &[
+        "creation_timestamp",
+        "update_timestamp",
+        "login_account",
+        "user_name",
+        "user_email",
+        "account_rule",
+        "account_status",
+    ],
+    date_column: "creation_timestamp",
+};
+
+/// 查詢用戶列表（分頁查詢，無提供參數則使用預設值）；
+/// search比對帳號、名稱與email，可依account_status、account_rule與建立時間（from、to）篩選，sort、order指定排序
 async fn list(
-    AdminToken(_): AdminToken,
+    RequirePermission(_, _): RequirePermission<UserRead>,
     Extension(ref db): Extension<Pool<Postgres>>,
     Query(params): Query<PaginationParams>,
+    Query(list_params): Query<ListParams>,
+    Query(filter): Query<AccountFilter>,
 ) -> impl IntoResponse {
-    let page = params.page.unwrap_or(1);
-    let count = params.count.unwrap_or(5);
-    let total = sqlx::query!("select count(*) from backendmodulesdb.user_info;")
-        .fetch_one(db)
-        .await
-        .unwrap()
-        .count
-        .unwrap_or(0) as u64;
-    let offset = pagination_offset(page, count);
-    match sqlx::query_as!(
-        UserInfo,
-        r#"select * from backendmodulesdb.user_info order by creation_timestamp limit $1 offset $2;"#,
-        count as i64,
-        offset as i64
-    )
-        .fetch_all(db)
+    match ListQuery::new(&USER_LIST, list_params)
+        .account_filter(filter)
+        .fetch_page::<UserInfo>(db, &params)
         .await
     {
-        Ok(result) => Ok(Json::from(PaginationResp::new(
-            total,
-            count,
-            page,
-            json!(result),
-        ))),
-        Err(error) => Err(get_db_err(error)),
+        Ok(result) => Ok(Json::from(result)),
+        Err(error) => Err(error),
     }
 }
 
 /// 儲存管理員資訊（有提供UUID的情況更新，無則新增）
 async fn save(
-    AdminToken(_): AdminToken,
+    RequirePermission(_, _): RequirePermission<UserWrite>,
     Extension(ref db): Extension<Pool<Postgres>>,
     CustomJsonRequest(params): CustomJsonRequest<UserInfo>,
 ) -> impl IntoResponse {
+    // 密碼需符合password設定的規則，以Argon2id雜湊後儲存
+    let login_password = if params.login_password.is_empty() {
+        None
+    } else {
+        check_password_policy(&params.login_password)?;
+        Some(hash_password(&params.login_password)?)
+    };
     if params.uuid == Uuid::default() {
+        let login_password = match login_password {
+            Some(login_password) => login_password,
+            None => {
+                return Err(err_json_gen(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Some("login_password is required when creating an account.".to_string()),
+                ))
+            }
+        };
         match sqlx::query!(
             "select count(*) from backendmodulesdb.user_info where login_account = $1",
             params.login_account
@@ -147,7 +176,7 @@ async fn save(
         "#,
                         Uuid::from(new_uuid_v1()),
                         params.login_account,
-                        params.login_password,
+                        login_password,
                         params.account_rule,
                         params.account_status,
                         params.user_name,
@@ -180,7 +209,7 @@ async fn save(
             r#"
         update backendmodulesdb.user_info
         set login_account = $2,
-            login_password = $3,
+            login_password = coalesce($3, login_password),
             account_rule = $4,
             account_status = $5,
             user_name = $6,
@@ -191,26 +220,60 @@ async fn save(
         "#,
             params.uuid,
             params.login_account,
-            params.login_password,
+            login_password,
             params.account_rule,
             params.account_status,
             params.user_name,
             params.user_email,
             params.note,
-            params.update_timestamp
+            now_local_time()
         )
         .fetch_one(db)
         .await;
         match query {
-            Ok(result) => Ok(Json::from(json!(result))),
+            Ok(result) => {
+                // 管理員變更密碼後撤銷該帳號所有的登入
+                if login_password.is_some() {
+                    revoke_account_sessions(db, TokenOwner::User, result.uuid).await?;
+                }
+                Ok(Json::from(json!(result)))
+            }
             Err(error) => Err(get_db_err(error)),
         }
     }
 }
 
+/// 用戶資訊可部分更新的欄位
+const USER_PATCH: AccountPatchSpec = AccountPatchSpec {
+    table: "backendmodulesdb.user_info",
+    owner: TokenOwner::User,
+    columns: &[
+        "login_account",
+        "account_rule",
+        "account_status",
+        "user_name",
+        "user_email",
+        "note",
+    ],
+};
+
+/// 部分更新用戶資訊（JSON Merge Patch，只更新有提供的欄位；login_password為null或未提供時不變更），
+/// 需附上查詢時取得的update_timestamp，資料已被其他請求修改時回傳409
+async fn partial_update(
+    RequirePermission(_, _): RequirePermission<UserWrite>,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    Path(uuid): Path<Uuid>,
+    CustomJsonRequest(patch): CustomJsonRequest<Value>,
+) -> impl IntoResponse {
+    match patch_account::<UserInfo>(db, &USER_PATCH, uuid, patch).await {
+        Ok(result) => Ok(Json::from(result)),
+        Err(error) => Err(error),
+    }
+}
+
 /// 移除管理員
 async fn remove(
-    AdminToken(_): AdminToken,
+    RequirePermission(_, _): RequirePermission<UserDelete>,
     Extension(ref db): Extension<Pool<Postgres>>,
     Path(uuid): Path<Uuid>,
 ) -> impl IntoResponse {
@@ -3,12 +3,10 @@ mod admin_service;
 mod user_service;
 
 use axum::{Extension, Router};
+use base_library::config::Config;
 use base_library::default_fallback;
-use dotenvy::dotenv;
 use mimalloc::MiMalloc;
 use sqlx::postgres::PgPoolOptions;
-use std::env;
-use std::net::SocketAddr;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -17,15 +15,12 @@ static GLOBAL: MiMalloc = MiMalloc;
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    dotenv().ok();
+    let config = Config::init(3001).expect("Configuration failed.");
 
     let db = PgPoolOptions::new()
-        .max_connections(80)
-        .connect(&*format!(
-            "postgresql://{}:{}@localhost:5432/postgres",
-            env::var("DB_USER").unwrap_or_else(|_| "postgres".parse().unwrap()),
-            env::var("DB_PASS").unwrap_or_else(|_| "postgres".parse().unwrap())
-        ))
+        .max_connections(config.database.max_connections)
+        .min_connections(config.database.min_connections)
+        .connect(&config.database.url())
         .await
         .expect("Database connection failed.");
 
@@ -35,7 +30,7 @@ async fn main() {
         .layer(Extension(db))
         .fallback(default_fallback);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3001));
+    let addr = config.bind_address();
 
     println!("Listening on {}", addr);
 
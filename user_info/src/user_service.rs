@@ -2,16 +2,24 @@ use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::routing::{get, post, put};
 use axum::{Extension, Json, Router};
-use jsonwebtoken::{encode, Header};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use sqlx::{Pool, Postgres};
 use time::OffsetDateTime;
 use uuid::Uuid;
 
+use base_library::account_token::{
+    consume_account_token, resend_allowed, send_token_mail, TokenPurpose,
+};
+use base_library::password::{
+    check_password_policy, hash_password, verify_password, PasswordCheck,
+};
+use base_library::token::{
+    issue_token_pair, revoke_account_sessions, revoke_session, rotate_refresh_token, TokenOwner,
+};
 use base_library::{
-    default_fallback, err_json_gen, get_db_err, get_jwt_exp_timestamp, new_uuid_v1, now_local_time,
-    Claims, CustomJsonRequest, UserToken, USER_KEY,
+    default_fallback, err_json_gen, get_db_err, new_uuid_v1, now_local_time, CustomJsonRequest,
+    UserToken,
 };
 
 pub fn router() -> Router {
@@ -20,8 +28,15 @@ pub fn router() -> Router {
         Router::new()
             .route("/query", get(query))
             .route("/save", put(save))
+            .route("/password", put(change_password))
             .route("/login", post(login))
+            .route("/refresh", post(refresh))
+            .route("/logout", post(logout))
             .route("/register", post(register))
+            .route("/verify", post(verify))
+            .route("/resendVerification", post(resend_verification))
+            .route("/requestPasswordReset", post(request_password_reset))
+            .route("/resetPassword", post(reset_password))
             .fallback(default_fallback),
     )
 }
@@ -37,6 +52,8 @@ struct UserInfo {
     #[serde(skip_deserializing, default)]
     uuid: Uuid,
     login_account: String,
+    /// 只在註冊時讀取，不會出現在回應內
+    #[serde(skip_serializing)]
     login_password: String,
     #[serde(skip_deserializing)]
     account_rule: i32,
@@ -45,6 +62,8 @@ struct UserInfo {
     user_name: String,
     user_email: String,
     note: Option<String>,
+    #[serde(skip_deserializing)]
+    email_verified: bool,
     #[serde(
         skip_deserializing,
         default = "now_local_time",
@@ -77,38 +96,120 @@ async fn query(
     }
 }
 
-/// 儲存用戶資訊（更新）
+#[derive(Deserialize)]
+struct ProfileReq {
+    user_name: String,
+    user_email: String,
+    note: Option<String>,
+}
+
+/// 儲存用戶資訊（更新個人資料，變更密碼請使用/user/password），
+/// 變更email時改為未驗證並寄出驗證信到新的email，完成驗證前無法登入
 async fn save(
     UserToken(user_token): UserToken,
     Extension(ref db): Extension<Pool<Postgres>>,
-    CustomJsonRequest(params): CustomJsonRequest<UserInfo>,
+    CustomJsonRequest(params): CustomJsonRequest<ProfileReq>,
 ) -> impl IntoResponse {
+    let previous_email = match sqlx::query!(
+        "select user_email from backendmodulesdb.user_info where uuid = $1",
+        user_token.uuid
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(record) => record.user_email,
+        Err(error) => return Err(get_db_err(error)),
+    };
+    let email_changed = previous_email != params.user_email;
     let query = sqlx::query_as!(
         UserInfo,
         r#"
         update backendmodulesdb.user_info
-        set login_password = $2,
-            user_name = $3,
-            user_email = $4,
-            note = $5,
-            update_timestamp = $6
+        set user_name = $2,
+            user_email = $3,
+            note = $4,
+            email_verified = email_verified and not $6,
+            update_timestamp = $5
         where uuid = $1 returning *;
         "#,
         user_token.uuid,
-        params.login_password,
         params.user_name,
         params.user_email,
         params.note,
-        params.update_timestamp
+        now_local_time(),
+        email_changed
     )
     .fetch_one(db)
     .await;
     match query {
-        Ok(result) => Ok(Json::from(json!(result))),
+        Ok(result) => {
+            if email_changed {
+                send_verification(db, &result).await?;
+            }
+            Ok(Json::from(json!(result)))
+        }
         Err(error) => Err(get_db_err(error)),
     }
 }
 
+#[derive(Deserialize)]
+struct ChangePasswordReq {
+    current_password: String,
+    new_password: String,
+}
+
+/// 變更密碼（需要目前的密碼，新密碼需符合password設定的規則），
+/// 完成後撤銷該帳號所有的登入並回傳新的Token
+async fn change_password(
+    UserToken(user_token): UserToken,
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(request): CustomJsonRequest<ChangePasswordReq>,
+) -> impl IntoResponse {
+    let user_info = match sqlx::query_as!(
+        UserInfo,
+        "select * from backendmodulesdb.user_info where uuid = $1",
+        user_token.uuid
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(user_info) => user_info,
+        Err(error) => return Err(get_db_err(error)),
+    };
+    if verify_password(&request.current_password, &user_info.login_password)
+        == PasswordCheck::Mismatched
+    {
+        return Err(err_json_gen(
+            StatusCode::FORBIDDEN,
+            Some("Current password is incorrect.".to_string()),
+        ));
+    }
+    if request.new_password == request.current_password {
+        return Err(err_json_gen(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some("New password must be different from the current password.".to_string()),
+        ));
+    }
+    check_password_policy(&request.new_password)?;
+    let login_password = hash_password(&request.new_password)?;
+    if let Err(error) = sqlx::query!(
+        "update backendmodulesdb.user_info set login_password = $2, update_timestamp = $3 where uuid = $1",
+        user_info.uuid,
+        login_password,
+        now_local_time()
+    )
+    .execute(db)
+    .await
+    {
+        return Err(get_db_err(error));
+    }
+    revoke_account_sessions(db, TokenOwner::User, user_info.uuid).await?;
+    match issue_token_pair(db, TokenOwner::User, user_info.uuid, None).await {
+        Ok(token_pair) => Ok(Json::from(json!(token_pair))),
+        Err(error) => Err(error),
+    }
+}
+
 async fn login(
     Extension(ref db): Extension<Pool<Postgres>>,
     CustomJsonRequest(request): CustomJsonRequest<LoginReq>,
@@ -135,12 +236,19 @@ async fn login(
     .fetch_all(db)
     .await
     {
-        Ok(admin_vec) => {
-            let successful_count = admin_vec
+        Ok(user_vec) => {
+            let password = request.password.unwrap();
+            let matched_vec: Vec<(&UserInfo, PasswordCheck)> = user_vec
                 .iter()
-                .filter(|user_info| user_info.login_password == request.password.clone().unwrap())
-                .count();
-            if successful_count > 1 {
+                .map(|user_info| {
+                    (
+                        user_info,
+                        verify_password(&password, &user_info.login_password),
+                    )
+                })
+                .filter(|(_, check)| *check != PasswordCheck::Mismatched)
+                .collect();
+            if matched_vec.len() > 1 {
                 Err(err_json_gen(
                     StatusCode::INTERNAL_SERVER_ERROR,
                     Some(
@@ -148,29 +256,48 @@ async fn login(
                             .to_string(),
                     ),
                 ))
-            } else if successful_count == 0 {
+            } else if matched_vec.is_empty() {
                 Err(err_json_gen(
                     StatusCode::UNAUTHORIZED,
                     Some("Couldn't found your account.".to_string()),
                 ))
             } else {
-                return if admin_vec.first().unwrap().account_status {
-                    let claims = Claims {
-                        uuid: admin_vec.first().unwrap().uuid.to_owned(),
-                        exp: get_jwt_exp_timestamp(),
+                let (user_info, check) = matched_vec.first().unwrap();
+                // 舊版明文密碼（或舊參數的雜湊）在登入成功時重新雜湊寫回
+                if *check == PasswordCheck::NeedsRehash {
+                    let login_password = match hash_password(&password) {
+                        Ok(login_password) => login_password,
+                        Err(error) => return Err(error),
                     };
-                    match encode(&Header::default(), &claims, &USER_KEY.encoding) {
-                        Ok(token) => Ok((StatusCode::OK, token)),
-                        Err(error) => Err(err_json_gen(
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            Some(error.to_string()),
-                        )),
+                    if let Err(error) = sqlx::query!(
+                        "update backendmodulesdb.user_info set login_password = $2 where uuid = $1",
+                        user_info.uuid,
+                        login_password
+                    )
+                    .execute(db)
+                    .await
+                    {
+                        return Err(get_db_err(error));
                     }
-                } else {
+                }
+                return if !user_info.account_status {
                     Err(err_json_gen(
                         StatusCode::UNAUTHORIZED,
                         Some("Account disabled. Please contact system administrator.".to_string()),
                     ))
+                } else if !user_info.email_verified {
+                    Err(err_json_gen(
+                        StatusCode::FORBIDDEN,
+                        Some(
+                            "Email address not verified. Please open the link in the verification mail or request a new one."
+                                .to_string(),
+                        ),
+                    ))
+                } else {
+                    match issue_token_pair(db, TokenOwner::User, user_info.uuid, None).await {
+                        Ok(token_pair) => Ok(Json::from(json!(token_pair))),
+                        Err(error) => Err(error),
+                    }
                 };
             }
         }
@@ -178,10 +305,16 @@ async fn login(
     }
 }
 
+/// 註冊（帳號需要驗證email後才能登入，註冊後寄出驗證信）
 async fn register(
     Extension(ref db): Extension<Pool<Postgres>>,
     CustomJsonRequest(request): CustomJsonRequest<UserInfo>,
 ) -> impl IntoResponse {
+    check_password_policy(&request.login_password)?;
+    let login_password = match hash_password(&request.login_password) {
+        Ok(login_password) => login_password,
+        Err(error) => return Err(error),
+    };
     match sqlx::query!(
         "select count(*) from backendmodulesdb.user_info where login_account = $1",
         request.login_account
@@ -200,6 +333,7 @@ async fn register(
                 login_password,
                 user_name,
                 user_email,
+                email_verified,
                 creation_timestamp,
                 update_timestamp
             )
@@ -209,13 +343,14 @@ async fn register(
                 $3,
                 $4,
                 $5,
+                false,
                 $6,
                 $7
             ) returning *;
         "#,
                     Uuid::from(new_uuid_v1()),
                     request.login_account,
-                    request.login_password,
+                    login_password,
                     request.user_name,
                     request.user_email,
                     request.creation_timestamp,
@@ -224,7 +359,10 @@ async fn register(
                 .fetch_one(db)
                 .await;
                 match query {
-                    Ok(result) => Ok(Json::from(json!(result))),
+                    Ok(result) => match send_verification(db, &result).await {
+                        Ok(()) => Ok(Json::from(json!(result))),
+                        Err(error) => Err(error),
+                    },
                     Err(error) => Err(get_db_err(error)),
                 }
             } else {
@@ -239,3 +377,233 @@ async fn register(
         Err(error) => Err(get_db_err(error)),
     }
 }
+
+/// 簽發驗證Token並在背景透過notification服務寄出驗證信（寄送失敗只記錄警告，用戶可要求重新寄送）
+async fn send_verification(
+    db: &Pool<Postgres>,
+    user_info: &UserInfo,
+) -> Result<(), (StatusCode, Json<Value>)> {
+    send_token_mail(
+        db,
+        TokenOwner::User,
+        user_info.uuid,
+        TokenPurpose::VerifyEmail,
+        &user_info.user_email,
+        &user_info.user_name,
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+struct VerifyReq {
+    token: String,
+}
+
+/// 驗證email（驗證信內的Token只能使用一次）
+async fn verify(
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(request): CustomJsonRequest<VerifyReq>,
+) -> impl IntoResponse {
+    let uuid = match consume_account_token(
+        db,
+        TokenOwner::User,
+        TokenPurpose::VerifyEmail,
+        &request.token,
+    )
+    .await
+    {
+        Ok(Some(uuid)) => uuid,
+        Ok(None) => {
+            return Err(err_json_gen(
+                StatusCode::BAD_REQUEST,
+                Some(
+                    "Verification token is invalid, used or expired. Please request a new verification mail."
+                        .to_string(),
+                ),
+            ))
+        }
+        Err(error) => return Err(error),
+    };
+    match sqlx::query!(
+        "update backendmodulesdb.user_info set email_verified = true, update_timestamp = $2 where uuid = $1",
+        uuid,
+        now_local_time()
+    )
+    .execute(db)
+    .await
+    {
+        Ok(_) => Ok((StatusCode::OK, uuid.to_string())),
+        Err(error) => Err(get_db_err(error)),
+    }
+}
+
+#[derive(Deserialize)]
+struct ResendVerificationReq {
+    account: String,
+}
+
+/// 重新寄送驗證信（同一帳號在account.resend_interval秒內只寄送一次），
+/// 無論帳號是否存在或已驗證都回傳相同結果，避免被用來探測帳號
+async fn resend_verification(
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(request): CustomJsonRequest<ResendVerificationReq>,
+) -> impl IntoResponse {
+    let user_info = match sqlx::query_as!(
+        UserInfo,
+        "select * from backendmodulesdb.user_info where login_account = $1 and email_verified = false",
+        request.account
+    )
+    .fetch_optional(db)
+    .await
+    {
+        Ok(user_info) => user_info,
+        Err(error) => return Err(get_db_err(error)),
+    };
+    if let Some(user_info) = user_info {
+        if resend_allowed(
+            db,
+            TokenOwner::User,
+            user_info.uuid,
+            TokenPurpose::VerifyEmail,
+        )
+        .await?
+        {
+            send_verification(db, &user_info).await?;
+        }
+    }
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "reason": "If the account exists and is not verified yet, a verification mail has been sent."
+        })),
+    ))
+}
+
+#[derive(Deserialize)]
+struct RequestPasswordResetReq {
+    account: String,
+}
+
+/// 要求重設密碼，寄出含一次性連結的郵件（同一帳號在account.resend_interval秒內只寄送一次），
+/// 無論帳號是否存在或已停用都回傳相同結果，避免被用來探測帳號
+async fn request_password_reset(
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(request): CustomJsonRequest<RequestPasswordResetReq>,
+) -> impl IntoResponse {
+    let user_info = match sqlx::query_as!(
+        UserInfo,
+        "select * from backendmodulesdb.user_info where login_account = $1 and account_status = true",
+        request.account
+    )
+    .fetch_optional(db)
+    .await
+    {
+        Ok(user_info) => user_info,
+        Err(error) => return Err(get_db_err(error)),
+    };
+    if let Some(user_info) = user_info {
+        if resend_allowed(
+            db,
+            TokenOwner::User,
+            user_info.uuid,
+            TokenPurpose::ResetPassword,
+        )
+        .await?
+        {
+            send_token_mail(
+                db,
+                TokenOwner::User,
+                user_info.uuid,
+                TokenPurpose::ResetPassword,
+                &user_info.user_email,
+                &user_info.user_name,
+            )
+            .await?;
+        }
+    }
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "reason": "If the account exists, a password reset mail has been sent."
+        })),
+    ))
+}
+
+#[derive(Deserialize)]
+struct ResetPasswordReq {
+    token: String,
+    password: String,
+}
+
+/// 以重設密碼郵件內的Token設定新密碼（Token只能使用一次），完成後撤銷該帳號的所有登入
+async fn reset_password(
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(request): CustomJsonRequest<ResetPasswordReq>,
+) -> impl IntoResponse {
+    check_password_policy(&request.password)?;
+    let login_password = hash_password(&request.password)?;
+    let uuid = match consume_account_token(
+        db,
+        TokenOwner::User,
+        TokenPurpose::ResetPassword,
+        &request.token,
+    )
+    .await?
+    {
+        Some(uuid) => uuid,
+        None => {
+            return Err(err_json_gen(
+                StatusCode::BAD_REQUEST,
+                Some(
+                    "Password reset token is invalid, used or expired. Please request a new password reset mail."
+                        .to_string(),
+                ),
+            ))
+        }
+    };
+    // 能收到重設密碼郵件也代表email有效
+    if let Err(error) = sqlx::query!(
+        r#"
+        update backendmodulesdb.user_info
+        set login_password = $2, email_verified = true, update_timestamp = $3
+        where uuid = $1;
+        "#,
+        uuid,
+        login_password,
+        now_local_time()
+    )
+    .execute(db)
+    .await
+    {
+        return Err(get_db_err(error));
+    }
+    revoke_account_sessions(db, TokenOwner::User, uuid).await?;
+    Ok((StatusCode::OK, uuid.to_string()))
+}
+
+#[derive(Deserialize)]
+struct RefreshReq {
+    refresh_token: String,
+}
+
+/// 以Refresh Token換發新的Token（舊的Refresh Token隨即失效）
+async fn refresh(
+    Extension(ref db): Extension<Pool<Postgres>>,
+    CustomJsonRequest(request): CustomJsonRequest<RefreshReq>,
+) -> impl IntoResponse {
+    match rotate_refresh_token(db, TokenOwner::User, &request.refresh_token).await {
+        Ok(token_pair) => Ok(Json::from(json!(token_pair))),
+        Err(error) => Err(error),
+    }
+}
+
+/// 登出（撤銷目前的Access Token與同一次登入換發的所有Refresh Token）
+async fn logout(
+    UserToken(user_token): UserToken,
+    Extension(ref db): Extension<Pool<Postgres>>,
+) -> impl IntoResponse {
+    match revoke_session(db, &user_token).await {
+        Ok(()) => Ok((StatusCode::OK, user_token.uuid.to_string())),
+        Err(error) => Err(error),
+    }
+}